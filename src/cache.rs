@@ -0,0 +1,279 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use git2::Repository;
+use serde::{Deserialize, Serialize};
+
+use crate::config::Config;
+use crate::environment::Environment;
+use crate::segment::{Segment, SegmentKind};
+
+// One file per repository, holding the segments from the last time it was summarized along
+// with what the repository looked like then.
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    key: String,
+    written: u64,
+    segments: Vec<CachedSegment>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CachedSegment {
+    index: usize,
+    segment: Segment,
+}
+
+// The segments from a previous run, if the repository still looks the same and they're recent
+// enough. Anything wrong with the file just means computing them again.
+pub fn read(
+    repository: &Repository,
+    config: &Config,
+    kinds: &[SegmentKind],
+    scope: Option<&Path>,
+    environment: &Environment,
+) -> Option<Vec<Option<Segment>>> {
+    let contents = fs::read_to_string(cache_path(repository, environment)?).ok()?;
+    let entry: Entry = toml::from_str(&contents).ok()?;
+
+    if entry.key != key(repository, config, kinds, scope)
+        || now().saturating_sub(entry.written) >= config.cache_seconds
+    {
+        return None;
+    }
+
+    let mut segments: Vec<Option<Segment>> = kinds.iter().map(|_| None).collect();
+    for cached in entry.segments {
+        *segments.get_mut(cached.index)? = Some(cached.segment);
+    }
+    Some(segments)
+}
+
+// Written to a temporary file first, so a prompt drawn at the same moment in another terminal
+// never reads half an entry.
+pub fn write(
+    repository: &Repository,
+    config: &Config,
+    kinds: &[SegmentKind],
+    scope: Option<&Path>,
+    environment: &Environment,
+    segments: Vec<Option<Segment>>,
+) -> Vec<Option<Segment>> {
+    let entry = Entry {
+        key: key(repository, config, kinds, scope),
+        written: now(),
+        segments: segments
+            .into_iter()
+            .enumerate()
+            .filter_map(|(index, segment)| {
+                Some(CachedSegment {
+                    index,
+                    segment: segment?,
+                })
+            })
+            .collect(),
+    };
+
+    if let (Some(path), Ok(contents)) =
+        (cache_path(repository, environment), toml::to_string(&entry))
+    {
+        let temporary = path.with_extension(format!("{}.tmp", process::id()));
+        let written = path
+            .parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|_| fs::write(&temporary, contents))
+            .and_then(|_| fs::rename(&temporary, &path));
+        if written.is_err() {
+            let _ = fs::remove_file(&temporary);
+        }
+    }
+
+    let mut segments: Vec<Option<Segment>> = kinds.iter().map(|_| None).collect();
+    for cached in entry.segments {
+        segments[cached.index] = Some(cached.segment);
+    }
+    segments
+}
+
+// Changes whenever HEAD moves, the index is written, or a merge or rebase leaves ORIG_HEAD
+// behind, as well as when the config or the requested segments change. Edits to tracked files
+// that don't touch the index don't change it, which is what `cache_seconds` is there to bound.
+// A status scoped to another directory is another key too.
+fn key(
+    repository: &Repository,
+    config: &Config,
+    kinds: &[SegmentKind],
+    scope: Option<&Path>,
+) -> String {
+    let git_dir = repository.path();
+    let mut hasher = DefaultHasher::new();
+
+    env!("CARGO_PKG_VERSION").hash(&mut hasher);
+    git_dir.hash(&mut hasher);
+    fs::read_to_string(git_dir.join("HEAD"))
+        .ok()
+        .hash(&mut hasher);
+    repository
+        .refname_to_id("HEAD")
+        .ok()
+        .map(|oid| oid.to_string())
+        .hash(&mut hasher);
+    fs::metadata(git_dir.join("index"))
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .hash(&mut hasher);
+    fs::read_to_string(git_dir.join("ORIG_HEAD"))
+        .ok()
+        .hash(&mut hasher);
+    config.fingerprint.hash(&mut hasher);
+    kinds.hash(&mut hasher);
+    scope.hash(&mut hasher);
+
+    format!("{:016x}", hasher.finish())
+}
+
+// In the daemon, the summaries of repositories it's watching are also kept in memory, for as long
+// as the watcher sees nothing change. No file on disk could know that.
+static MEMORY: Mutex<Option<Memory>> = Mutex::new(None);
+
+#[derive(Default)]
+struct Memory {
+    watched: HashSet<PathBuf>,
+    entries: HashMap<MemoryKey, Remembered>,
+}
+
+struct Remembered {
+    written: Instant,
+    segments: Vec<Option<Segment>>,
+}
+
+impl Memory {
+    const LIMIT: usize = 256;
+    // A commit's age goes on changing with nothing in the repository changing.
+    const COMMIT_AGE_LIFETIME: Duration = Duration::from_secs(60);
+}
+
+// The git dir, the config's fingerprint, the segments and the status scope.
+type MemoryKey = (PathBuf, u64, Vec<SegmentKind>, Option<PathBuf>);
+
+fn memory_key(
+    repository: &Repository,
+    config: &Config,
+    kinds: &[SegmentKind],
+    scope: Option<&Path>,
+) -> MemoryKey {
+    (
+        repository.path().to_path_buf(),
+        config.fingerprint,
+        kinds.to_vec(),
+        scope.map(Path::to_path_buf),
+    )
+}
+
+// Called by the watcher once it has the whole repository covered.
+pub fn keep_in_memory(git_dir: &Path) {
+    let mut memory = MEMORY.lock().unwrap_or_else(|error| error.into_inner());
+    memory
+        .get_or_insert_with(Memory::default)
+        .watched
+        .insert(git_dir.to_path_buf());
+}
+
+// Called by the watcher when it stops watching the repository.
+pub fn stop_keeping_in_memory(git_dir: &Path) {
+    forget(git_dir);
+    if let Some(memory) = MEMORY
+        .lock()
+        .unwrap_or_else(|error| error.into_inner())
+        .as_mut()
+    {
+        memory.watched.remove(git_dir);
+    }
+}
+
+// Called by the watcher when something in the repository changes.
+pub fn forget(git_dir: &Path) {
+    if let Some(memory) = MEMORY
+        .lock()
+        .unwrap_or_else(|error| error.into_inner())
+        .as_mut()
+    {
+        memory
+            .entries
+            .retain(|(other, _, _, _), _| other != git_dir);
+    }
+}
+
+pub fn recall(
+    repository: &Repository,
+    config: &Config,
+    kinds: &[SegmentKind],
+    scope: Option<&Path>,
+) -> Option<Vec<Option<Segment>>> {
+    let memory = MEMORY.lock().unwrap_or_else(|error| error.into_inner());
+    let remembered = memory
+        .as_ref()?
+        .entries
+        .get(&memory_key(repository, config, kinds, scope))?;
+
+    if kinds.contains(&SegmentKind::CommitAge)
+        && remembered.written.elapsed() >= Memory::COMMIT_AGE_LIFETIME
+    {
+        return None;
+    }
+
+    Some(remembered.segments.clone())
+}
+
+pub fn remember(
+    repository: &Repository,
+    config: &Config,
+    kinds: &[SegmentKind],
+    scope: Option<&Path>,
+    segments: &[Option<Segment>],
+) {
+    let mut memory = MEMORY.lock().unwrap_or_else(|error| error.into_inner());
+    let memory = match memory.as_mut() {
+        Some(memory) if memory.watched.contains(repository.path()) => memory,
+        _ => return,
+    };
+
+    if memory.entries.len() >= Memory::LIMIT {
+        memory.entries.clear();
+    }
+    memory.entries.insert(
+        memory_key(repository, config, kinds, scope),
+        Remembered {
+            written: Instant::now(),
+            segments: segments.to_vec(),
+        },
+    );
+}
+
+fn cache_path(repository: &Repository, environment: &Environment) -> Option<PathBuf> {
+    let cache_home = match environment.var_os("XDG_CACHE_HOME") {
+        Some(cache_home) if !cache_home.is_empty() => PathBuf::from(cache_home),
+        _ => PathBuf::from(environment.var_os("HOME")?).join(".cache"),
+    };
+
+    let mut hasher = DefaultHasher::new();
+    repository.path().hash(&mut hasher);
+
+    Some(
+        cache_home
+            .join("my-fancy-zsh-git-prompt")
+            .join(format!("{:016x}.toml", hasher.finish())),
+    )
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
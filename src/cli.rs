@@ -0,0 +1,161 @@
+use std::path::PathBuf;
+
+use clap::builder::PossibleValuesParser;
+use clap::{Parser, Subcommand, ValueEnum, ValueHint};
+
+use crate::themes;
+
+#[derive(Parser)]
+#[command(
+    version,
+    about = "A fancy git prompt for zsh, bash, fish and PowerShell"
+)]
+pub struct Cli {
+    /// Shell to format the prompt for
+    #[arg(long, value_enum, default_value = "zsh", global = true)]
+    pub shell: Shell,
+
+    /// What to print the summary for
+    #[arg(long, value_enum, default_value = "prompt", global = true)]
+    pub format: Format,
+
+    /// Directory to describe, instead of the current directory
+    #[arg(long, global = true, value_hint = ValueHint::DirPath)]
+    pub path: Option<PathBuf>,
+
+    /// Print the prompt without any colors or other styling
+    #[arg(long, global = true)]
+    pub no_color: bool,
+
+    /// Config file to use, instead of the one in the config directory
+    #[arg(long, global = true, value_hint = ValueHint::FilePath)]
+    pub config: Option<PathBuf>,
+
+    /// Theme to start from, before applying the config
+    #[arg(long, global = true, value_parser = PossibleValuesParser::new(themes::names()))]
+    pub theme: Option<String>,
+
+    /// Exit status of the last command, like `$?`, for the exit status segment
+    #[arg(long, global = true, allow_negative_numbers = true)]
+    pub last_exit_code: Option<i32>,
+
+    /// How long the last command took, for the command duration segment
+    #[arg(long, global = true)]
+    pub cmd_duration_ms: Option<u64>,
+
+    /// The zsh keymap in use, for the vi mode indicator
+    #[arg(long, value_enum, global = true)]
+    pub keymap: Option<Keymap>,
+
+    /// Use plain ASCII instead of unicode symbols
+    #[arg(long, global = true)]
+    pub ascii: bool,
+
+    /// Give up on the work tree scan after this long, marking the branch instead
+    #[arg(long, global = true)]
+    pub timeout_ms: Option<u64>,
+
+    /// Report how long each step of working out the prompt took, on stderr
+    #[arg(long, global = true)]
+    pub timings: bool,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Print the prompt for the directory (the default)
+    Prompt {
+        /// Print only one side of the prompt, or both separated by a NUL byte
+        #[arg(long, value_enum)]
+        side: Option<Side>,
+        /// Print a quick prompt, then write the full one to this file in the background
+        #[arg(long, requires = "async_pid", value_hint = ValueHint::FilePath)]
+        async_file: Option<PathBuf>,
+        /// Process to send SIGUSR1 once the full prompt is written
+        #[arg(long, requires = "async_file")]
+        async_pid: Option<i32>,
+    },
+    /// Print shell code that sets up the prompt, to `eval` from the shell's startup file
+    Init {
+        /// Shell to set up, instead of the one `--shell` names
+        #[arg(value_enum, value_name = "SHELL")]
+        init_shell: Option<Shell>,
+        /// Start a daemon and ask it for each prompt
+        #[arg(long)]
+        daemon: bool,
+        /// Draw a quick prompt first and redraw once the full one is ready (zsh only)
+        #[arg(long, conflicts_with = "daemon")]
+        two_phase: bool,
+    },
+    /// Print the location of the config file
+    Config,
+    /// Keep repositories open and answer `query` over a Unix socket
+    Daemon {
+        /// Socket to listen on, instead of the default
+        #[arg(long, value_hint = ValueHint::FilePath)]
+        socket: Option<PathBuf>,
+    },
+    /// Like `prompt`, but answered by a running daemon when there is one
+    Query {
+        /// Print only one side of the prompt, or both separated by a NUL byte
+        #[arg(long, value_enum)]
+        side: Option<Side>,
+        /// Socket the daemon listens on, instead of the default
+        #[arg(long, value_hint = ValueHint::FilePath)]
+        socket: Option<PathBuf>,
+    },
+    /// Render the prompt repeatedly and report how long it takes
+    Bench {
+        /// How many times to render it
+        #[arg(long, default_value_t = 100, value_parser = clap::value_parser!(u32).range(1..))]
+        iterations: u32,
+    },
+    /// Print a completion script for the shell
+    Completions {
+        /// Shell to complete for, instead of the one `--shell` names
+        #[arg(value_enum, value_name = "SHELL")]
+        completions_shell: Option<Shell>,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Shell {
+    Zsh,
+    Bash,
+    Fish,
+    Pwsh,
+    /// Plain ANSI escape sequences, for scripts and status bars
+    Ansi,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Format {
+    /// A shell prompt, for whichever shell `--shell` names
+    Prompt,
+    /// tmux status line styles, for `status-left` or `status-right`
+    Tmux,
+    /// A JSON object describing the repository, for other tools to read
+    Json,
+    /// A Starship format string, for a `custom` module
+    Starship,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Side {
+    /// The segments for `PROMPT`
+    Left,
+    /// The segments for `RPROMPT`
+    Right,
+    /// The left side, a NUL byte, then the right side
+    Both,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Keymap {
+    /// Vi command mode
+    Vicmd,
+    /// Vi insert mode
+    Viins,
+}
@@ -0,0 +1,40 @@
+// Every C library has it, but the `libc` crate doesn't declare it.
+#[cfg(unix)]
+extern "C" {
+    fn strftime(
+        buffer: *mut libc::c_char,
+        size: libc::size_t,
+        format: *const libc::c_char,
+        time: *const libc::tm,
+    ) -> libc::size_t;
+}
+
+// The current local time, formatted with `strftime`.
+#[cfg(unix)]
+pub fn format_now(format: &str) -> Option<String> {
+    let format = std::ffi::CString::new(format).ok()?;
+    let mut buffer = [0u8; 256];
+
+    let length = unsafe {
+        let now = libc::time(std::ptr::null_mut());
+        let mut local: libc::tm = std::mem::zeroed();
+        if libc::localtime_r(&now, &mut local).is_null() {
+            return None;
+        }
+
+        strftime(
+            buffer.as_mut_ptr() as *mut libc::c_char,
+            buffer.len(),
+            format.as_ptr(),
+            &local,
+        )
+    };
+
+    String::from_utf8(buffer[..length].to_vec()).ok()
+}
+
+// Without the C library's time zone handling, there's no local time to show.
+#[cfg(not(unix))]
+pub fn format_now(_format: &str) -> Option<String> {
+    None
+}
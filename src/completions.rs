@@ -0,0 +1,320 @@
+use clap::{Arg, Command, CommandFactory, ValueHint};
+
+use crate::cli::{Cli, Shell};
+
+// What an option or positional argument can be completed with.
+enum Values {
+    Flag,
+    Free,
+    Choices(Vec<String>),
+    File,
+    Directory,
+}
+
+struct Opt {
+    long: String,
+    help: String,
+    values: Values,
+}
+
+struct Sub {
+    name: String,
+    help: String,
+    options: Vec<Opt>,
+    positional: Option<Values>,
+}
+
+fn values(arg: &Arg) -> Values {
+    if !arg.get_action().takes_values() {
+        return Values::Flag;
+    }
+
+    let choices: Vec<String> = arg
+        .get_possible_values()
+        .iter()
+        .filter(|value| !value.is_hide_set())
+        .map(|value| value.get_name().to_string())
+        .collect();
+    if !choices.is_empty() {
+        return Values::Choices(choices);
+    }
+
+    match arg.get_value_hint() {
+        ValueHint::DirPath => Values::Directory,
+        ValueHint::FilePath | ValueHint::AnyPath => Values::File,
+        _ => Values::Free,
+    }
+}
+
+fn first_line(help: Option<&clap::builder::StyledStr>) -> String {
+    help.map(|help| help.to_string())
+        .and_then(|help| help.lines().next().map(str::to_string))
+        .unwrap_or_default()
+}
+
+fn options(command: &Command) -> Vec<Opt> {
+    command
+        .get_arguments()
+        .filter(|arg| !arg.is_hide_set())
+        .filter_map(|arg| {
+            Some(Opt {
+                long: arg.get_long()?.to_string(),
+                help: first_line(arg.get_help()),
+                values: values(arg),
+            })
+        })
+        .collect()
+}
+
+// Read from the command line definition, so new flags are picked up without touching this.
+fn subcommands(command: &Command) -> Vec<Sub> {
+    command
+        .get_subcommands()
+        .filter(|subcommand| !subcommand.is_hide_set())
+        .map(|subcommand| Sub {
+            name: subcommand.get_name().to_string(),
+            help: first_line(subcommand.get_about()),
+            options: options(subcommand),
+            positional: subcommand.get_positionals().next().map(values),
+        })
+        .collect()
+}
+
+pub fn print_completions(shell: Shell) {
+    let mut command = Cli::command();
+    command.build();
+    let name = command.get_name().to_string();
+    let options = options(&command);
+    let subcommands = subcommands(&command);
+
+    match shell {
+        Shell::Zsh => print!("{}", zsh(&name, &options, &subcommands)),
+        Shell::Bash => print!("{}", bash(&name, &options, &subcommands)),
+        Shell::Fish => print!("{}", fish(&name, &options, &subcommands)),
+        Shell::Pwsh | Shell::Ansi => {
+            eprintln!("my-fancy-zsh-git-prompt: there are no completions for that shell")
+        }
+    }
+}
+
+fn function_name(name: &str) -> String {
+    format!("_{}", name.replace('-', "_"))
+}
+
+// Inside a single-quoted `_arguments` spec, where brackets and colons also mean something.
+fn zsh_quote(text: &str) -> String {
+    text.replace('\'', "'\\''")
+        .replace('[', "\\[")
+        .replace(']', "\\]")
+        .replace(':', "\\:")
+}
+
+fn zsh_action(values: &Values) -> String {
+    match values {
+        Values::Flag => String::new(),
+        Values::Free => ": ".to_string(),
+        Values::Choices(choices) => format!(":value:({})", choices.join(" ")),
+        Values::File => ":file:_files".to_string(),
+        Values::Directory => ":directory:_files -/".to_string(),
+    }
+}
+
+fn zsh_specs(options: &[Opt]) -> Vec<String> {
+    options
+        .iter()
+        .map(|option| {
+            format!(
+                "'--{}[{}]{}'",
+                option.long,
+                zsh_quote(&option.help),
+                zsh_action(&option.values)
+            )
+        })
+        .collect()
+}
+
+fn zsh(name: &str, options: &[Opt], subcommands: &[Sub]) -> String {
+    let function = function_name(name);
+    let mut script = format!("#compdef {}\n\n{}() {{\n", name, function);
+    script.push_str("  local state\n  _arguments -C \\\n");
+    for spec in zsh_specs(options) {
+        script.push_str(&format!("    {} \\\n", spec));
+    }
+
+    let commands: Vec<String> = subcommands
+        .iter()
+        .map(|subcommand| {
+            // The list is evaluated, so the descriptions are double-quoted words in it.
+            let help: String = subcommand
+                .help
+                .chars()
+                .flat_map(|c| match c {
+                    '"' | '`' | '$' | '\\' => vec!['\\', c],
+                    c => vec![c],
+                })
+                .collect();
+            format!("{}\\:\"{}\"", subcommand.name, zsh_quote(&help))
+        })
+        .collect();
+    script.push_str(&format!(
+        "    '1:command:(({}))' \\\n    '*::arg:->args'\n\n",
+        commands.join(" ")
+    ));
+
+    script.push_str("  case $state in\n    args)\n      case $words[1] in\n");
+    for subcommand in subcommands {
+        let mut specs = zsh_specs(&subcommand.options);
+        if let Some(ref positional) = subcommand.positional {
+            specs.push(format!("'1{}'", zsh_action(positional)));
+        }
+        script.push_str(&format!(
+            "        {}) _arguments {} ;;\n",
+            subcommand.name,
+            specs.join(" ")
+        ));
+    }
+    script.push_str("      esac\n      ;;\n  esac\n}\n\n");
+    script.push_str(&format!("{} \"$@\"\n", function));
+
+    script
+}
+
+fn bash_words(options: &[Opt], positional: Option<&Values>) -> String {
+    let mut words: Vec<String> = options
+        .iter()
+        .map(|option| format!("--{}", option.long))
+        .collect();
+    if let Some(Values::Choices(choices)) = positional {
+        words.extend(choices.iter().cloned());
+    }
+    words.join(" ")
+}
+
+fn bash(name: &str, options: &[Opt], subcommands: &[Sub]) -> String {
+    let function = function_name(name);
+    let names: Vec<&str> = subcommands
+        .iter()
+        .map(|subcommand| subcommand.name.as_str())
+        .collect();
+
+    let mut script = format!("{}() {{\n", function);
+    script.push_str("  local cur=${COMP_WORDS[COMP_CWORD]} prev=${COMP_WORDS[COMP_CWORD-1]}\n");
+    script.push_str("  local command= word\n");
+    script.push_str("  for word in \"${COMP_WORDS[@]:1:COMP_CWORD-1}\"; do\n");
+    script.push_str(&format!(
+        "    case $word in\n      {}) command=$word; break ;;\n    esac\n  done\n\n",
+        names.join("|")
+    ));
+
+    // Every subcommand sees the global options too, so theirs cover the top level's.
+    let all_options = options.iter().chain(
+        subcommands
+            .iter()
+            .flat_map(|subcommand| &subcommand.options),
+    );
+    let mut seen = Vec::new();
+    script.push_str("  case $prev in\n");
+    for option in all_options {
+        if seen.contains(&option.long) {
+            continue;
+        }
+        seen.push(option.long.clone());
+
+        let reply = match option.values {
+            Values::Flag => continue,
+            Values::Free => "return".to_string(),
+            Values::Choices(ref choices) => format!(
+                "COMPREPLY=($(compgen -W \"{}\" -- \"$cur\")); return",
+                choices.join(" ")
+            ),
+            Values::File => "COMPREPLY=($(compgen -f -- \"$cur\")); return".to_string(),
+            Values::Directory => "COMPREPLY=($(compgen -d -- \"$cur\")); return".to_string(),
+        };
+        script.push_str(&format!("    --{}) {} ;;\n", option.long, reply));
+    }
+    script.push_str("  esac\n\n  local words\n  case $command in\n");
+
+    script.push_str(&format!(
+        "    \"\") words=\"{} {}\" ;;\n",
+        names.join(" "),
+        bash_words(options, None)
+    ));
+    for subcommand in subcommands {
+        script.push_str(&format!(
+            "    {}) words=\"{}\" ;;\n",
+            subcommand.name,
+            bash_words(&subcommand.options, subcommand.positional.as_ref())
+        ));
+    }
+    script.push_str("  esac\n  COMPREPLY=($(compgen -W \"$words\" -- \"$cur\"))\n}\n\n");
+    script.push_str(&format!("complete -F {} {}\n", function, name));
+
+    script
+}
+
+fn fish_quote(text: &str) -> String {
+    format!("'{}'", text.replace('\\', "\\\\").replace('\'', "\\'"))
+}
+
+fn fish_values(values: &Values) -> String {
+    match values {
+        Values::Flag => String::new(),
+        Values::Free => " -x".to_string(),
+        Values::Choices(choices) => format!(" -xa {}", fish_quote(&choices.join(" "))),
+        Values::File => " -rF".to_string(),
+        Values::Directory => " -xa '(__fish_complete_directories)'".to_string(),
+    }
+}
+
+fn fish(name: &str, options: &[Opt], subcommands: &[Sub]) -> String {
+    let mut script = format!("complete -c {} -f\n", name);
+
+    for subcommand in subcommands {
+        script.push_str(&format!(
+            "complete -c {} -n __fish_use_subcommand -a {} -d {}\n",
+            name,
+            subcommand.name,
+            fish_quote(&subcommand.help)
+        ));
+    }
+
+    for option in options {
+        script.push_str(&format!(
+            "complete -c {} -l {} -d {}{}\n",
+            name,
+            option.long,
+            fish_quote(&option.help),
+            fish_values(&option.values)
+        ));
+    }
+
+    for subcommand in subcommands {
+        let condition = format!("-n '__fish_seen_subcommand_from {}'", subcommand.name);
+
+        // The global options are already offered everywhere.
+        for option in &subcommand.options {
+            if options.iter().any(|global| global.long == option.long) {
+                continue;
+            }
+            script.push_str(&format!(
+                "complete -c {} {} -l {} -d {}{}\n",
+                name,
+                condition,
+                option.long,
+                fish_quote(&option.help),
+                fish_values(&option.values)
+            ));
+        }
+
+        if let Some(ref positional) = subcommand.positional {
+            script.push_str(&format!(
+                "complete -c {} {}{}\n",
+                name,
+                condition,
+                fish_values(positional)
+            ));
+        }
+    }
+
+    script
+}
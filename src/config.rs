@@ -0,0 +1,915 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+
+use git2::{ConfigLevel, Repository};
+use regex::Regex;
+use serde::{Deserialize, Deserializer};
+
+use crate::environment::Environment;
+use crate::rules::Rule;
+use crate::segment::SegmentKind;
+use crate::symbols::SymbolSet;
+use crate::themes;
+use crate::zsh_output::TermColor;
+
+const ENV_PREFIX: &str = "MFZGP_";
+const REPOSITORY_CONFIG_FILE_NAME: &str = ".my-fancy-prompt.toml";
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Truncation {
+    Tail,
+    Middle,
+}
+
+// Where the status comes from. libgit2 is quickest to start, but only the `git` command can ask a
+// filesystem monitor what changed or read a sparse index, so `auto` uses it when the repository
+// has either set up.
+#[derive(Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Backend {
+    Auto,
+    Libgit2,
+    Git,
+}
+
+// How the path segment shows the working directory.
+#[derive(Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PathStyle {
+    // The directory's name, or inside a repository, the repository's name and the way down from
+    // it.
+    Repository,
+    // Only the directory's name.
+    Basename,
+    // The whole path, with the home directory shortened to `~` and any of the
+    // `named_directories` to `~name`, like zsh's `%~`.
+    Full,
+    // The last `path_components` of the full path.
+    Trailing,
+    // The full path with the directories on the way cut down to `path_abbreviation_length`
+    // characters, keeping the repository's name and the directory's own, like fish.
+    Fish,
+}
+
+// A zsh color: a name like `red`, a number from the terminal's 256-color palette, or a
+// `#rrggbb` hex value.
+pub struct Color(String);
+
+impl From<&str> for Color {
+    fn from(name: &str) -> Self {
+        Color(name.to_string())
+    }
+}
+
+impl Deref for Color {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl<'de> Deserialize<'de> for Color {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let name = match toml::Value::deserialize(deserializer)? {
+            toml::Value::String(name) => name,
+            toml::Value::Integer(number) => number.to_string(),
+            other => other.to_string(),
+        };
+
+        match TermColor::parse(&name) {
+            Some(_) => Ok(Color(name)),
+            None => Err(serde::de::Error::custom(format!(
+                "expected a color name, 0-255 or #rrggbb, found {}",
+                name
+            ))),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub segments: Vec<SegmentKind>,
+    // For `prompt --side`. Without them, the left side is the path and the right is the rest.
+    pub left_segments: Option<Vec<SegmentKind>>,
+    pub right_segments: Option<Vec<SegmentKind>>,
+    pub separator: String,
+    #[serde(deserialize_with = "deserialize_flag")]
+    pub powerline: bool,
+    pub backgrounds: HashMap<SegmentKind, Color>,
+    pub styles: HashMap<SegmentKind, String>,
+    pub format: Option<String>,
+    pub rules: Vec<Rule>,
+    pub symbols: SymbolSet,
+    pub staged_symbol: String,
+    pub unstaged_symbol: String,
+    pub untracked_symbol: String,
+    // Next to the branch when the work tree scan ran out of time.
+    pub dirty_unknown_symbol: String,
+    // Next to the branch when the status only covers the current directory.
+    pub scope_symbol: String,
+    pub conflicted_symbol: String,
+    pub deleted_symbol: String,
+    pub renamed_symbol: String,
+    pub detached_symbol: String,
+    pub ahead_symbol: String,
+    pub behind_symbol: String,
+    pub sha_length: usize,
+    #[serde(deserialize_with = "deserialize_flag")]
+    pub show_sha: bool,
+    pub branch_max_length: Option<usize>,
+    pub branch_truncation: Truncation,
+    #[serde(deserialize_with = "deserialize_regexes")]
+    pub branch_strip_patterns: Vec<Regex>,
+    #[serde(deserialize_with = "deserialize_flag")]
+    pub show_stripped_prefix: bool,
+    #[serde(deserialize_with = "deserialize_optional_regex")]
+    pub ticket_pattern: Option<Regex>,
+    pub ticket_url: Option<String>,
+    pub protected_branches: Vec<String>,
+    #[serde(deserialize_with = "deserialize_flag")]
+    pub nerd_font: bool,
+    pub upstream_symbol: String,
+    pub tag_symbol: String,
+    pub upstream_gone_symbol: String,
+    pub diverged_symbol: String,
+    pub unpushed_total_symbol: String,
+    pub behind_default_branch_symbol: String,
+    pub submodule_symbol: String,
+    pub lfs_symbol: String,
+    pub sparse_symbol: String,
+    pub identity_symbol: String,
+    pub signing_symbol: String,
+    pub index_lock_symbol: String,
+    pub skips_symbol: String,
+    pub stash_symbol: String,
+    pub powerline_symbol: String,
+    pub ellipsis_symbol: String,
+    pub insertions_symbol: String,
+    pub deletions_symbol: String,
+    pub commit_subject_symbol: String,
+    pub exit_status_symbol: String,
+    pub vicmd_symbol: String,
+    pub viins_symbol: String,
+    pub prompt_char_symbol: String,
+    pub root_prompt_char_symbol: String,
+    pub command_duration_threshold_ms: u64,
+    #[serde(deserialize_with = "deserialize_flag")]
+    pub show_file_counts: bool,
+    #[serde(deserialize_with = "deserialize_flag")]
+    pub show_untracked: bool,
+    // Past this many files in the index, untracked files aren't looked for.
+    pub untracked_max_index_entries: Option<usize>,
+    // Only count changes beneath the current directory, which in a big repository is much less
+    // to look at.
+    #[serde(deserialize_with = "deserialize_flag")]
+    pub scope_status_to_directory: bool,
+    pub commit_age_warning_seconds: u64,
+    pub commit_age_stale_seconds: u64,
+    pub commit_subject_length: usize,
+    // OSC 8 links on the branch and path, and the ticket when there's a `ticket_url`.
+    #[serde(deserialize_with = "deserialize_flag")]
+    pub hyperlinks: bool,
+    // Tells the terminal the working directory with OSC 7, so new tabs and splits can open there.
+    #[serde(deserialize_with = "deserialize_flag")]
+    pub report_directory: bool,
+    // A `format`-style template for the terminal title. `true` is short for
+    // `{repository_name}:{branch}`.
+    pub title: Option<String>,
+    // How long a repository's summary can be reused while HEAD, the index and ORIG_HEAD stay the
+    // same. Off at 0, since edits that haven't been staged go unnoticed until it runs out.
+    pub cache_seconds: u64,
+    // How long the work tree scan and the other slow lookups get before the prompt is drawn
+    // without them.
+    pub timeout_ms: Option<u64>,
+    // Where to stop looking for a repository, on top of `GIT_CEILING_DIRECTORIES`.
+    pub ceiling_directories: Vec<PathBuf>,
+    pub backend: Backend,
+    pub path_style: PathStyle,
+    pub path_components: usize,
+    pub path_abbreviation_length: usize,
+    // In characters, past which directories are left out of the path.
+    pub path_max_width: Option<usize>,
+    // Like zsh's `hash -d name=path`, to show the directory as `~name`.
+    pub named_directories: BTreeMap<String, PathBuf>,
+    // Identifies the settings that produced this config, so cached summaries from other settings
+    // aren't reused.
+    #[serde(skip)]
+    pub fingerprint: u64,
+    // A `strftime` format for the clock segment.
+    pub clock_format: String,
+    pub bare_color: Color,
+    pub not_repo_color: Color,
+    pub repository_name_color: Color,
+    pub merge_color: Color,
+    pub revert_color: Color,
+    pub cherry_pick_color: Color,
+    pub bisect_color: Color,
+    pub rebase_color: Color,
+    pub am_color: Color,
+    pub unborn_branch_color: Color,
+    pub clean_branch_color: Color,
+    pub dirty_branch_color: Color,
+    pub dirty_unknown_color: Color,
+    pub scope_color: Color,
+    pub protected_branch_color: Color,
+    pub gone_branch_color: Color,
+    pub stripped_prefix_color: Color,
+    pub upstream_color: Color,
+    pub upstream_gone_color: Color,
+    pub staged_color: Color,
+    pub unstaged_color: Color,
+    pub untracked_color: Color,
+    pub deleted_color: Color,
+    pub renamed_color: Color,
+    pub submodule_color: Color,
+    pub lfs_color: Color,
+    pub insertions_color: Color,
+    pub deletions_color: Color,
+    pub ticket_color: Color,
+    pub tag_color: Color,
+    pub ahead_behind_color: Color,
+    pub diverged_color: Color,
+    pub behind_default_branch_color: Color,
+    pub commit_age_color: Color,
+    pub commit_age_warning_color: Color,
+    pub commit_age_stale_color: Color,
+    pub commit_subject_color: Color,
+    pub conflicted_color: Color,
+    pub unpushed_total_color: Color,
+    pub index_lock_color: Color,
+    pub skips_color: Color,
+    pub signing_color: Color,
+    pub signing_missing_key_color: Color,
+    pub identity_color: Color,
+    pub sparse_color: Color,
+    pub stash_color: Color,
+    pub exit_status_color: Color,
+    pub command_duration_color: Color,
+    pub vicmd_color: Color,
+    pub viins_color: Color,
+    pub user_host_color: Color,
+    pub root_user_host_color: Color,
+    pub prompt_char_color: Color,
+    pub root_prompt_char_color: Color,
+    pub clock_color: Color,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            segments: vec![
+                SegmentKind::ExitStatus,
+                SegmentKind::CommandDuration,
+                SegmentKind::Path,
+                SegmentKind::HostIcon,
+                SegmentKind::Operation,
+                SegmentKind::Branch,
+                SegmentKind::Dirty,
+                SegmentKind::Stash,
+                SegmentKind::Lfs,
+                SegmentKind::Ticket,
+                SegmentKind::Tag,
+                SegmentKind::AheadBehind,
+                SegmentKind::BehindDefaultBranch,
+                SegmentKind::Conflicts,
+                SegmentKind::IndexLock,
+                SegmentKind::Skips,
+                SegmentKind::Signing,
+                SegmentKind::Identity,
+                SegmentKind::Sparse,
+                SegmentKind::Keymap,
+            ],
+            left_segments: None,
+            right_segments: None,
+            separator: " ".to_string(),
+            powerline: false,
+            backgrounds: HashMap::new(),
+            styles: HashMap::new(),
+            format: None,
+            rules: Vec::new(),
+            symbols: SymbolSet::Unicode,
+            staged_symbol: "+".to_string(),
+            unstaged_symbol: "*".to_string(),
+            untracked_symbol: "…".to_string(),
+            dirty_unknown_symbol: "?".to_string(),
+            scope_symbol: "◎".to_string(),
+            conflicted_symbol: "✖".to_string(),
+            deleted_symbol: "✘".to_string(),
+            renamed_symbol: "»".to_string(),
+            detached_symbol: "➦".to_string(),
+            ahead_symbol: "↑".to_string(),
+            behind_symbol: "↓".to_string(),
+            sha_length: 7,
+            show_sha: false,
+            branch_max_length: None,
+            branch_truncation: Truncation::Tail,
+            branch_strip_patterns: Vec::new(),
+            show_stripped_prefix: false,
+            ticket_pattern: None,
+            ticket_url: None,
+            protected_branches: vec!["main".to_string(), "master".to_string()],
+            nerd_font: false,
+            upstream_symbol: "→".to_string(),
+            tag_symbol: "⌂".to_string(),
+            upstream_gone_symbol: "✗".to_string(),
+            diverged_symbol: "⇕".to_string(),
+            unpushed_total_symbol: "⇡*".to_string(),
+            behind_default_branch_symbol: "⇣".to_string(),
+            submodule_symbol: "◧".to_string(),
+            lfs_symbol: "LFS↯".to_string(),
+            sparse_symbol: "◌".to_string(),
+            identity_symbol: "✉!".to_string(),
+            signing_symbol: "🔏".to_string(),
+            index_lock_symbol: "🔒".to_string(),
+            skips_symbol: "(skips)".to_string(),
+            stash_symbol: "≡".to_string(),
+            powerline_symbol: "▶".to_string(),
+            ellipsis_symbol: "…".to_string(),
+            insertions_symbol: "+".to_string(),
+            deletions_symbol: "−".to_string(),
+            commit_subject_symbol: "—".to_string(),
+            exit_status_symbol: "✘".to_string(),
+            vicmd_symbol: "❮".to_string(),
+            viins_symbol: "❯".to_string(),
+            prompt_char_symbol: "❯".to_string(),
+            root_prompt_char_symbol: "#".to_string(),
+            command_duration_threshold_ms: 2000,
+            show_file_counts: true,
+            show_untracked: true,
+            untracked_max_index_entries: Some(100_000),
+            scope_status_to_directory: false,
+            commit_age_warning_seconds: 60 * 60 * 24,
+            commit_age_stale_seconds: 60 * 60 * 24 * 7,
+            commit_subject_length: 30,
+            hyperlinks: false,
+            report_directory: false,
+            title: None,
+            cache_seconds: 0,
+            timeout_ms: None,
+            ceiling_directories: Vec::new(),
+            backend: Backend::Auto,
+            path_style: PathStyle::Repository,
+            path_components: 2,
+            path_abbreviation_length: 1,
+            path_max_width: None,
+            named_directories: BTreeMap::new(),
+            fingerprint: 0,
+            clock_format: "%H:%M:%S".to_string(),
+            bare_color: "cyan".into(),
+            not_repo_color: "blue".into(),
+            repository_name_color: "cyan".into(),
+            merge_color: "magenta".into(),
+            revert_color: "magenta".into(),
+            cherry_pick_color: "magenta".into(),
+            bisect_color: "magenta".into(),
+            rebase_color: "magenta".into(),
+            am_color: "magenta".into(),
+            unborn_branch_color: "yellow".into(),
+            clean_branch_color: "blue".into(),
+            dirty_branch_color: "red".into(),
+            dirty_unknown_color: "8".into(),
+            scope_color: "8".into(),
+            protected_branch_color: "yellow".into(),
+            gone_branch_color: "yellow".into(),
+            stripped_prefix_color: "8".into(),
+            upstream_color: "cyan".into(),
+            upstream_gone_color: "red".into(),
+            staged_color: "green".into(),
+            unstaged_color: "red".into(),
+            untracked_color: "red".into(),
+            deleted_color: "red".into(),
+            renamed_color: "cyan".into(),
+            submodule_color: "yellow".into(),
+            lfs_color: "yellow".into(),
+            insertions_color: "green".into(),
+            deletions_color: "red".into(),
+            ticket_color: "magenta".into(),
+            tag_color: "yellow".into(),
+            ahead_behind_color: "blue".into(),
+            diverged_color: "red".into(),
+            behind_default_branch_color: "yellow".into(),
+            commit_age_color: "green".into(),
+            commit_age_warning_color: "yellow".into(),
+            commit_age_stale_color: "red".into(),
+            commit_subject_color: "white".into(),
+            conflicted_color: "red".into(),
+            unpushed_total_color: "cyan".into(),
+            index_lock_color: "yellow".into(),
+            skips_color: "8".into(),
+            signing_color: "8".into(),
+            signing_missing_key_color: "red".into(),
+            identity_color: "8".into(),
+            sparse_color: "yellow".into(),
+            stash_color: "yellow".into(),
+            exit_status_color: "red".into(),
+            command_duration_color: "yellow".into(),
+            vicmd_color: "yellow".into(),
+            viins_color: "green".into(),
+            user_host_color: "green".into(),
+            root_user_host_color: "red".into(),
+            prompt_char_color: "default".into(),
+            root_prompt_char_color: "red".into(),
+            clock_color: "8".into(),
+        }
+    }
+}
+
+// What the command line has to say about loading the config, and the environment it was run in.
+pub struct LoadOptions<'a> {
+    pub environment: &'a Environment,
+    pub path: Option<&'a Path>,
+    pub theme: Option<&'a str>,
+    pub ascii: bool,
+    pub timeout_ms: Option<u64>,
+}
+
+impl Config {
+    // Settings are layered: the theme, then the user's config file, then the repository's own
+    // overrides, then environment variables. An explicitly requested file is always read, so a
+    // typo in the path gets reported rather than silently falling back to the defaults.
+    pub fn load(options: &LoadOptions, repository: Option<&Repository>) -> Self {
+        let environment = options.environment;
+        let mut table = match (options.path, config_path(environment)) {
+            (Some(path), _) => read_table(&resolve(environment, path)),
+            (None, Some(path)) if path.is_file() => read_table(&path),
+            _ => toml::Table::new(),
+        };
+        let mut disabled = vec![];
+
+        if let Some(repository) = repository {
+            apply_repository_overrides(repository, &mut table, &mut disabled);
+        }
+        apply_env_overrides(&mut table, &mut disabled, environment);
+        apply_theme(&mut table, options.theme);
+        if let Some(timeout_ms) = options.timeout_ms {
+            let timeout_ms = toml::Value::Integer(timeout_ms as i64);
+            table.insert("timeout_ms".to_string(), timeout_ms);
+        }
+
+        if options.ascii || wants_ascii(&table, environment) {
+            apply_ascii(&mut table);
+        } else {
+            apply_symbol_set(&mut table);
+        }
+        apply_hyperlinks(&mut table, environment);
+        apply_title(&mut table);
+
+        let mut hasher = DefaultHasher::new();
+        table.to_string().hash(&mut hasher);
+        let fingerprint = hasher.finish();
+
+        match toml::Value::Table(table).try_into::<Config>() {
+            Ok(mut config) => {
+                config.fingerprint = fingerprint;
+                config.segments.retain(|kind| !disabled.contains(kind));
+                let sides = config.left_segments.iter_mut();
+                for segments in sides.chain(config.right_segments.iter_mut()) {
+                    segments.retain(|kind| !disabled.contains(kind));
+                }
+                config
+            }
+            Err(error) => {
+                eprintln!("my-fancy-zsh-git-prompt: {}", error);
+                Config::default()
+            }
+        }
+    }
+}
+
+// The theme can come from the command line or from any layer of the config.
+fn apply_theme(table: &mut toml::Table, theme: Option<&str>) {
+    let name = match (theme, table.remove("theme")) {
+        (Some(name), _) => name.to_string(),
+        (None, Some(toml::Value::String(name))) => name,
+        (None, Some(other)) => {
+            eprintln!(
+                "my-fancy-zsh-git-prompt: expected a theme name, found {}",
+                other
+            );
+            return;
+        }
+        (None, None) => return,
+    };
+
+    match themes::theme(&name) {
+        Some(theme_table) => {
+            for (key, value) in theme_table {
+                table.entry(key).or_insert(value);
+            }
+        }
+        None => eprintln!("my-fancy-zsh-git-prompt: unknown theme {}", name),
+    }
+}
+
+// Fills in every symbol that wasn't set individually from the chosen symbol set.
+fn apply_symbol_set(table: &mut toml::Table) {
+    let symbol_set = match table.get("symbols").cloned().map(toml::Value::try_into) {
+        Some(Ok(symbol_set)) => symbol_set,
+        // Leave a bad value in place so deserializing reports it.
+        Some(Err(_)) => return,
+        None => SymbolSet::Unicode,
+    };
+
+    for (key, symbol) in symbol_set.symbols() {
+        table
+            .entry(key.to_string())
+            .or_insert_with(|| toml::Value::String(symbol.to_string()));
+    }
+
+    if let SymbolSet::NerdFont = symbol_set {
+        table
+            .entry("nerd_font")
+            .or_insert(toml::Value::Boolean(true));
+    }
+}
+
+// `ascii = true` asks for it outright; otherwise it's used when the terminal can't show unicode.
+fn wants_ascii(table: &toml::Table, environment: &Environment) -> bool {
+    match table.get("ascii") {
+        Some(value) => is_truthy(value),
+        None => !terminal_supports_unicode(environment),
+    }
+}
+
+// A locale that isn't UTF-8, or a dumb terminal. An unset locale gets the benefit of the doubt.
+fn terminal_supports_unicode(environment: &Environment) -> bool {
+    if environment.var("TERM") == Some("dumb") {
+        return false;
+    }
+
+    let locale = ["LC_ALL", "LC_CTYPE", "LANG"]
+        .iter()
+        .find_map(|name| environment.var(name));
+
+    match locale {
+        Some(locale) => {
+            let locale = locale.to_lowercase();
+            locale.contains("utf-8") || locale.contains("utf8")
+        }
+        None => true,
+    }
+}
+
+// Left unset, hyperlinks are used when the terminal is known to handle them, since elsewhere the
+// escapes can show up as garbage.
+fn apply_hyperlinks(table: &mut toml::Table, environment: &Environment) {
+    if !table.contains_key("hyperlinks") {
+        let supported = terminal_supports_hyperlinks(environment);
+        table.insert("hyperlinks".to_string(), toml::Value::Boolean(supported));
+    }
+}
+
+fn apply_title(table: &mut toml::Table) {
+    match table.get("title") {
+        Some(toml::Value::Boolean(true)) => {
+            let title = "{repository_name}:{branch}".to_string();
+            table.insert("title".to_string(), toml::Value::String(title));
+        }
+        Some(toml::Value::Boolean(false)) => {
+            table.remove("title");
+        }
+        _ => {}
+    }
+}
+
+// tmux and screen drop OSC 8 unless they've been set up to pass it through.
+fn terminal_supports_hyperlinks(environment: &Environment) -> bool {
+    let var = |name| environment.var(name);
+
+    if var("TMUX").is_some() || var("STY").is_some() {
+        return false;
+    }
+
+    if let Some(term_program) = var("TERM_PROGRAM") {
+        if ["iTerm.app", "WezTerm", "vscode", "Hyper", "ghostty"].contains(&term_program) {
+            return true;
+        }
+    }
+
+    if let Some(vte_version) = var("VTE_VERSION") {
+        return vte_version
+            .parse::<u32>()
+            .is_ok_and(|version| version >= 5000);
+    }
+
+    ["KITTY_WINDOW_ID", "WT_SESSION", "KONSOLE_VERSION"]
+        .iter()
+        .any(|name| var(name).is_some())
+        || var("TERM").is_some_and(|term| ["xterm-kitty", "foot", "alacritty"].contains(&term))
+}
+
+// Swaps every symbol for its ASCII version, even ones that were set individually, since they
+// can't be drawn either way.
+fn apply_ascii(table: &mut toml::Table) {
+    for (key, symbol) in SymbolSet::Ascii.symbols() {
+        table.insert(key.to_string(), toml::Value::String(symbol.to_string()));
+    }
+
+    table.insert("nerd_font".to_string(), toml::Value::Boolean(false));
+}
+
+fn read_table(path: &Path) -> toml::Table {
+    let result = fs::read_to_string(path)
+        .map_err(|error| error.to_string())
+        .and_then(|contents| {
+            contents
+                .parse()
+                .map_err(|error: toml::de::Error| error.to_string())
+        });
+
+    match result {
+        Ok(table) => table,
+        Err(error) => {
+            eprintln!("my-fancy-zsh-git-prompt: {}: {}", path.display(), error);
+            toml::Table::new()
+        }
+    }
+}
+
+// A `.my-fancy-prompt.toml` at the root of the work tree, then a `[prompt]` section in the
+// repository's own `.git/config`, where keys use dashes (`prompt.show-sha = true`). Both come
+// with the repository, so both are sanitized, and both can disable segments.
+fn apply_repository_overrides(
+    repository: &Repository,
+    table: &mut toml::Table,
+    disabled: &mut Vec<SegmentKind>,
+) {
+    if let Some(workdir) = repository.workdir() {
+        let path = workdir.join(REPOSITORY_CONFIG_FILE_NAME);
+
+        if path.is_file() {
+            for (key, value) in read_table(&path) {
+                apply_repository_setting(table, disabled, &key, &key, value);
+            }
+        }
+    }
+
+    let local_config = match repository
+        .config()
+        .and_then(|config| config.open_level(ConfigLevel::Local))
+    {
+        Ok(config) => config,
+        Err(_) => return,
+    };
+    let entries = match local_config.entries(Some(r"^prompt\.")) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+
+    for entry in &entries {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(_) => continue,
+        };
+
+        if let (Some(name), Some(raw)) = (entry.name(), entry.value()) {
+            let key = name["prompt.".len()..].replace('-', "_");
+            let value = parse_override_value(raw);
+            apply_repository_setting(table, disabled, name, &key, value);
+        }
+    }
+}
+
+fn apply_repository_setting(
+    table: &mut toml::Table,
+    disabled: &mut Vec<SegmentKind>,
+    name: &str,
+    key: &str,
+    mut value: toml::Value,
+) {
+    sanitize(&mut value);
+    apply_setting(table, disabled, name, key, value);
+}
+
+// Anything in the repository arrives with whatever was cloned, so it can't be allowed to smuggle
+// expansions or terminal sequences into the prompt through symbols or separators.
+fn sanitize(value: &mut toml::Value) {
+    match value {
+        toml::Value::String(text) => {
+            text.retain(|c| !matches!(c, '$' | '`') && !c.is_control());
+        }
+        toml::Value::Array(values) => values.iter_mut().for_each(sanitize),
+        toml::Value::Table(table) => table.iter_mut().for_each(|(_, value)| sanitize(value)),
+        _ => {}
+    }
+}
+
+// Every setting can be overridden with an `MFZGP_<SETTING>` variable, and any segment can be
+// dropped with `MFZGP_DISABLE_<SEGMENT>=1`. Anything else in the environment is none of our
+// business, even when it isn't unicode.
+fn apply_env_overrides(
+    table: &mut toml::Table,
+    disabled: &mut Vec<SegmentKind>,
+    environment: &Environment,
+) {
+    for (name, raw) in environment.vars_os() {
+        let (name, key) = match name.to_str() {
+            Some(name) => match name.strip_prefix(ENV_PREFIX) {
+                Some(key) if !key.is_empty() => (name, key.to_lowercase()),
+                _ => continue,
+            },
+            None => continue,
+        };
+
+        match raw.to_str() {
+            Some(raw) => apply_override(table, disabled, name, &key, raw),
+            None => eprintln!("my-fancy-zsh-git-prompt: {}: not unicode", name),
+        }
+    }
+}
+
+fn apply_override(
+    table: &mut toml::Table,
+    disabled: &mut Vec<SegmentKind>,
+    name: &str,
+    key: &str,
+    raw: &str,
+) {
+    apply_setting(table, disabled, name, key, parse_override_value(raw));
+}
+
+fn apply_setting(
+    table: &mut toml::Table,
+    disabled: &mut Vec<SegmentKind>,
+    name: &str,
+    key: &str,
+    value: toml::Value,
+) {
+    if let Some(segment) = key.strip_prefix("disable_") {
+        if !is_truthy(&value) {
+            return;
+        }
+
+        match SegmentKind::from_name(segment) {
+            // The dirty segment counts untracked files too, and it's the looking for them that
+            // costs, so that stops as well.
+            Some(SegmentKind::Untracked) => {
+                disabled.push(SegmentKind::Untracked);
+                table.insert("show_untracked".to_string(), toml::Value::Boolean(false));
+            }
+            Some(kind) => disabled.push(kind),
+            None => eprintln!("my-fancy-zsh-git-prompt: {}: unknown segment", name),
+        }
+    } else {
+        table.insert(key.to_string(), value);
+    }
+}
+
+// Values are read as TOML when they parse (so numbers and lists work), otherwise as plain strings.
+fn parse_override_value(raw: &str) -> toml::Value {
+    format!("value = {}", raw)
+        .parse::<toml::Table>()
+        .ok()
+        .and_then(|mut table| table.remove("value"))
+        .unwrap_or_else(|| toml::Value::String(raw.to_string()))
+}
+
+fn is_truthy(value: &toml::Value) -> bool {
+    match value {
+        toml::Value::Boolean(flag) => *flag,
+        toml::Value::Integer(number) => *number != 0,
+        toml::Value::String(text) => matches!(text.as_str(), "yes" | "on"),
+        _ => false,
+    }
+}
+
+pub fn config_path(environment: &Environment) -> Option<PathBuf> {
+    let config_home = match environment.var_os("XDG_CONFIG_HOME") {
+        Some(config_home) if !config_home.is_empty() => PathBuf::from(config_home),
+        _ => PathBuf::from(environment.var_os("HOME")?).join(".config"),
+    };
+
+    Some(
+        config_home
+            .join("my-fancy-zsh-git-prompt")
+            .join("config.toml"),
+    )
+}
+
+// A relative path is from the working directory the prompt is for, which in the daemon isn't its
+// own.
+pub fn resolve(environment: &Environment, path: &Path) -> PathBuf {
+    match environment.current_dir() {
+        Some(directory) => directory.join(path),
+        None => path.to_path_buf(),
+    }
+}
+
+fn deserialize_regexes<'de, D>(deserializer: D) -> Result<Vec<Regex>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|pattern| Regex::new(pattern).map_err(serde::de::Error::custom))
+        .collect()
+}
+
+fn deserialize_optional_regex<'de, D>(deserializer: D) -> Result<Option<Regex>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Option::<String>::deserialize(deserializer)?
+        .map(|pattern| Regex::new(&pattern).map_err(serde::de::Error::custom))
+        .transpose()
+}
+
+// Accepts `1`/`0` as well as `true`/`false`, which is what people tend to put in environment
+// variables.
+fn deserialize_flag<'de, D>(deserializer: D) -> Result<bool, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let value = toml::Value::deserialize(deserializer)?;
+
+    match value {
+        toml::Value::Boolean(_) | toml::Value::Integer(0) | toml::Value::Integer(1) => {
+            Ok(is_truthy(&value))
+        }
+        _ => Err(serde::de::Error::custom(format!(
+            "expected a boolean, found {}",
+            value
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn overridden(name: &str, raw: &str) -> (Config, Vec<SegmentKind>) {
+        let mut table = toml::Table::new();
+        let mut disabled = Vec::new();
+        let key = name[ENV_PREFIX.len()..].to_lowercase();
+        apply_override(&mut table, &mut disabled, name, &key, raw);
+
+        (toml::Value::Table(table).try_into().unwrap(), disabled)
+    }
+
+    #[test]
+    fn overrides_are_read_as_toml() {
+        let (config, _) = overridden("MFZGP_SHA_LENGTH", "12");
+        assert_eq!(config.sha_length, 12);
+
+        let (config, _) = overridden("MFZGP_SEPARATOR", " | ");
+        assert_eq!(config.separator, " | ");
+    }
+
+    #[test]
+    fn disabling_a_segment_leaves_the_rest() {
+        let (config, disabled) = overridden("MFZGP_DISABLE_STASH", "1");
+
+        assert!(disabled.contains(&SegmentKind::Stash));
+        assert!(config.show_untracked);
+    }
+
+    #[test]
+    fn disabling_untracked_stops_looking_for_untracked_files() {
+        let (config, disabled) = overridden("MFZGP_DISABLE_UNTRACKED", "1");
+
+        assert!(disabled.contains(&SegmentKind::Untracked));
+        assert!(!config.show_untracked);
+    }
+
+    #[test]
+    fn both_repository_sources_are_sanitized_and_can_disable_segments() {
+        let path = std::env::temp_dir().join(format!("mfzgp-{}-overrides", std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        let repository = Repository::init(&path).unwrap();
+        let contents = "disable_stash = true\nseparator = \"$(id)\"\n";
+        fs::write(path.join(REPOSITORY_CONFIG_FILE_NAME), contents).unwrap();
+        let mut local_config = repository.config().unwrap();
+        local_config
+            .set_str("prompt.staged-symbol", "`id`")
+            .unwrap();
+        local_config.set_str("prompt.disable-tag", "1").unwrap();
+
+        let mut table = toml::Table::new();
+        let mut disabled = Vec::new();
+        apply_repository_overrides(&repository, &mut table, &mut disabled);
+        let _ = fs::remove_dir_all(&path);
+
+        assert_eq!(table["separator"].as_str(), Some("(id)"));
+        assert_eq!(table["staged_symbol"].as_str(), Some("id"));
+        assert!(disabled.contains(&SegmentKind::Stash));
+        assert!(disabled.contains(&SegmentKind::Tag));
+    }
+
+    #[test]
+    fn a_false_disable_does_nothing() {
+        let (config, disabled) = overridden("MFZGP_DISABLE_UNTRACKED", "0");
+
+        assert!(disabled.is_empty());
+        assert!(config.show_untracked);
+    }
+}
@@ -0,0 +1,464 @@
+use std::path::Path;
+
+use crate::cli::{Cli, Side};
+use crate::environment::Environment;
+
+#[cfg(unix)]
+use std::collections::HashMap;
+#[cfg(unix)]
+use std::env;
+#[cfg(unix)]
+use std::ffi::{OsStr, OsString};
+#[cfg(unix)]
+use std::fs;
+#[cfg(unix)]
+use std::io::{Read, Write};
+#[cfg(unix)]
+use std::net::Shutdown;
+#[cfg(unix)]
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+#[cfg(unix)]
+use std::os::unix::fs::PermissionsExt;
+#[cfg(unix)]
+use std::os::unix::io::AsRawFd;
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+#[cfg(unix)]
+use std::path::PathBuf;
+#[cfg(unix)]
+use std::sync::mpsc::{self, Sender};
+#[cfg(unix)]
+use std::sync::Arc;
+#[cfg(unix)]
+use std::thread;
+#[cfg(unix)]
+use std::time::Duration;
+
+#[cfg(unix)]
+use clap::Parser;
+#[cfg(unix)]
+use git2::Repository;
+
+#[cfg(unix)]
+use crate::cli::Command;
+#[cfg(unix)]
+use crate::config::{self, Config};
+#[cfg(unix)]
+use crate::runtime_dir;
+#[cfg(unix)]
+use crate::watcher::{Wake, Watcher};
+
+// How long a client gets to send its request and to take the answer. `query` gives the daemon
+// about as long as the prompt would be allowed here, `timeout_ms` and a little over for the rest
+// of it, before working the prompt out itself, or a while longer without a `timeout_ms`.
+#[cfg(unix)]
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(1);
+#[cfg(unix)]
+const ANSWER_MARGIN: Duration = Duration::from_millis(100);
+#[cfg(unix)]
+const ANSWER_TIMEOUT: Duration = Duration::from_secs(2);
+
+// A request is NUL-terminated fields: the version, the working directory, the environment as
+// `KEY=VALUE` entries, an empty field, then the command line. The response is `0` followed by
+// the prompt, or anything else when the client should work the prompt out itself.
+#[cfg(unix)]
+#[derive(Clone)]
+struct Request {
+    version: OsString,
+    directory: PathBuf,
+    environment: Vec<(OsString, OsString)>,
+    arguments: Vec<OsString>,
+}
+
+#[cfg(unix)]
+impl Request {
+    fn new(environment: &Environment) -> Option<Self> {
+        Some(Request {
+            version: env!("CARGO_PKG_VERSION").into(),
+            directory: environment.current_dir()?.to_path_buf(),
+            environment: environment
+                .vars_os()
+                .map(|(key, value)| (key.to_os_string(), value.to_os_string()))
+                .collect(),
+            arguments: env::args_os().collect(),
+        })
+    }
+
+    // What the prompt is worked out in, instead of the daemon's own environment and directory.
+    fn environment(&self) -> Environment {
+        Environment::new(
+            self.environment.iter().cloned(),
+            Some(self.directory.clone()),
+        )
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        let mut push = |field: &OsStr| {
+            bytes.extend_from_slice(field.as_bytes());
+            bytes.push(0);
+        };
+
+        push(&self.version);
+        push(self.directory.as_os_str());
+        for (key, value) in &self.environment {
+            let mut entry = key.clone();
+            entry.push("=");
+            entry.push(value);
+            push(&entry);
+        }
+        push(OsStr::new(""));
+        for argument in &self.arguments {
+            push(argument);
+        }
+
+        bytes
+    }
+
+    fn decode(bytes: &[u8]) -> Option<Self> {
+        let mut fields = bytes
+            .strip_suffix(&[0])?
+            .split(|byte| *byte == 0)
+            .map(|field| OsString::from_vec(field.to_vec()));
+
+        let version = fields.next()?;
+        let directory = PathBuf::from(fields.next()?);
+        let mut environment = Vec::new();
+        for entry in fields.by_ref() {
+            if entry.is_empty() {
+                break;
+            }
+            let entry = entry.into_vec();
+            let split = entry.iter().position(|byte| *byte == b'=')?;
+            environment.push((
+                OsString::from_vec(entry[..split].to_vec()),
+                OsString::from_vec(entry[split + 1..].to_vec()),
+            ));
+        }
+
+        Some(Request {
+            version,
+            directory,
+            environment,
+            arguments: fields.collect(),
+        })
+    }
+}
+
+// Repositories stay open between prompts, so there's no discovery walk and libgit2 keeps its
+// caches. A prompt being worked out has the repository to itself, and one asked for meanwhile
+// opens another. Nothing is evicted individually; the whole lot is dropped once there are too
+// many.
+#[cfg(unix)]
+#[derive(Default)]
+struct Repositories {
+    open: HashMap<PathBuf, Repository>,
+}
+
+#[cfg(unix)]
+impl Repositories {
+    const LIMIT: usize = 64;
+
+    fn take(&mut self, directory: &Path) -> Option<Repository> {
+        self.open
+            .remove(directory)
+            .filter(|repository| repository.path().exists())
+    }
+
+    fn put_back(&mut self, directory: PathBuf, repository: Repository) {
+        if self.open.len() >= Self::LIMIT {
+            self.open.clear();
+        }
+        self.open.entry(directory).or_insert(repository);
+    }
+}
+
+// `$XDG_RUNTIME_DIR` is private to the user already, and the socket has always gone straight in it.
+#[cfg(unix)]
+fn socket_path() -> Option<PathBuf> {
+    if let Some(runtime_dir) = env::var_os("XDG_RUNTIME_DIR").filter(|dir| !dir.is_empty()) {
+        return Some(PathBuf::from(runtime_dir).join("my-fancy-zsh-git-prompt.sock"));
+    }
+
+    Some(runtime_dir::private_dir()?.join("daemon.sock"))
+}
+
+// What the daemon keeps between requests. Where there's a watcher, the last request for each
+// repository it watches is answered again whenever something changes, so the next prompt finds
+// the summary already worked out.
+#[cfg(unix)]
+struct Daemon {
+    repositories: Repositories,
+    watcher: Option<Watcher>,
+    last_requests: HashMap<PathBuf, Request>,
+}
+
+// What the main loop hears about: a request read from a client, or a prompt worked out on another
+// thread, along with the repository it was worked out in, to keep for next time.
+#[cfg(unix)]
+enum Message {
+    Request(Request, UnixStream),
+    Answered {
+        request: Request,
+        directory: PathBuf,
+        repository: Option<Repository>,
+    },
+}
+
+// Where the other threads send their messages. There's a byte on the bell for each, for the
+// watcher to wait on alongside its own events. Neither end blocks: a full bell is already
+// ringing.
+#[cfg(unix)]
+#[derive(Clone)]
+struct Mailbox {
+    sender: Sender<Message>,
+    ringer: Arc<UnixStream>,
+}
+
+#[cfg(unix)]
+impl Mailbox {
+    fn send(&self, message: Message) {
+        if self.sender.send(message).is_ok() {
+            let _ = (&*self.ringer).write(&[0]);
+        }
+    }
+}
+
+// Requests are read on threads of their own, so a client that never finishes sending one only
+// holds up itself, and each prompt is worked out on a thread of its own, so a slow repository
+// only holds up its own prompts. The open repositories and the watcher stay here, and are handed
+// out and given back by message.
+#[cfg(unix)]
+pub fn serve(socket: Option<&Path>) {
+    let path = match socket.map(Path::to_path_buf).or_else(socket_path) {
+        Some(path) => path,
+        None => return eprintln!("my-fancy-zsh-git-prompt: there's nowhere safe for the socket"),
+    };
+
+    if UnixStream::connect(&path).is_ok() {
+        return eprintln!(
+            "my-fancy-zsh-git-prompt: a daemon is already listening on {}",
+            path.display()
+        );
+    }
+    let _ = fs::remove_file(&path);
+
+    let listener = match UnixListener::bind(&path) {
+        Ok(listener) => listener,
+        Err(error) => {
+            return eprintln!(
+                "my-fancy-zsh-git-prompt: can't listen on {}: {}",
+                path.display(),
+                error
+            )
+        }
+    };
+    let _ = fs::set_permissions(&path, fs::Permissions::from_mode(0o600));
+
+    let (bell, ringer) = match UnixStream::pair() {
+        Ok(pair) => pair,
+        Err(error) => return eprintln!("my-fancy-zsh-git-prompt: {}", error),
+    };
+    let _ = bell.set_nonblocking(true);
+    let _ = ringer.set_nonblocking(true);
+    let (sender, receiver) = mpsc::channel();
+    let mailbox = Mailbox {
+        sender,
+        ringer: Arc::new(ringer),
+    };
+    let requests = mailbox.clone();
+    thread::spawn(move || read_requests(listener, requests));
+
+    let mut daemon = Daemon {
+        repositories: Repositories::default(),
+        watcher: Watcher::new(),
+        last_requests: HashMap::new(),
+    };
+    loop {
+        let wake = match daemon.watcher {
+            Some(ref mut watcher) => watcher.wait(bell.as_raw_fd()),
+            None => Wake::Connection,
+        };
+        if let Wake::Changed(git_dirs) = wake {
+            for git_dir in git_dirs {
+                if let Some(request) = daemon.last_requests.get(&git_dir).cloned() {
+                    respond(request, None, &mut daemon, &mailbox);
+                }
+            }
+            continue;
+        }
+
+        let messages: Vec<Message> = if daemon.watcher.is_some() {
+            while (&bell).read(&mut [0; 64]).is_ok_and(|length| length > 0) {}
+            receiver.try_iter().collect()
+        } else {
+            match receiver.recv() {
+                Ok(message) => vec![message],
+                Err(_) => return,
+            }
+        };
+        for message in messages {
+            match message {
+                Message::Request(request, stream) => {
+                    respond(request, Some(stream), &mut daemon, &mailbox)
+                }
+                Message::Answered {
+                    request,
+                    directory,
+                    repository: Some(repository),
+                } => {
+                    if let Some(ref mut watcher) = daemon.watcher {
+                        watcher.watch(&repository);
+                    }
+                    daemon
+                        .last_requests
+                        .insert(repository.path().to_path_buf(), request);
+                    daemon.repositories.put_back(directory, repository);
+                }
+                Message::Answered { .. } => {}
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+fn read_requests(listener: UnixListener, mailbox: Mailbox) {
+    for stream in listener.incoming() {
+        let mut stream = match stream {
+            Ok(stream) => stream,
+            Err(_) => continue,
+        };
+        let mailbox = mailbox.clone();
+
+        thread::spawn(move || {
+            let timeouts = stream
+                .set_read_timeout(Some(CLIENT_TIMEOUT))
+                .and_then(|_| stream.set_write_timeout(Some(CLIENT_TIMEOUT)));
+            let mut bytes = Vec::new();
+            if timeouts.is_err() || stream.read_to_end(&mut bytes).is_err() {
+                return;
+            }
+
+            match Request::decode(&bytes) {
+                Some(request) => mailbox.send(Message::Request(request, stream)),
+                None => {
+                    let _ = stream.write_all(b"1");
+                }
+            }
+        });
+    }
+}
+
+#[cfg(not(unix))]
+pub fn serve(_socket: Option<&Path>) {
+    eprintln!("my-fancy-zsh-git-prompt: the daemon needs Unix domain sockets");
+}
+
+// Answers the client, if there is one, on a thread of its own. Without one, the prompt is only
+// worked out to have its summary ready for the next.
+#[cfg(unix)]
+fn respond(request: Request, stream: Option<UnixStream>, daemon: &mut Daemon, mailbox: &Mailbox) {
+    let (cli, side, environment, dir_path) = match parse(&request) {
+        Ok(parsed) => parsed,
+        Err(error) => {
+            eprintln!("my-fancy-zsh-git-prompt: {}", error);
+            if let Some(mut stream) = stream {
+                let _ = stream.write_all(b"1");
+            }
+            return;
+        }
+    };
+
+    let config_path = cli
+        .config
+        .clone()
+        .or_else(|| config::config_path(&environment));
+    if let (Some(ref mut watcher), Some(config_path)) = (&mut daemon.watcher, config_path) {
+        watcher.watch_config(&config::resolve(&environment, &config_path));
+    }
+
+    let repository = daemon.repositories.take(&dir_path);
+    let mailbox = mailbox.clone();
+    thread::spawn(move || {
+        let repository =
+            repository.or_else(|| crate::discover_repository(&cli, &environment, &dir_path));
+        // Picks up anything written to the index since the last prompt.
+        if let Some(Ok(mut index)) = repository.as_ref().map(Repository::index) {
+            let _ = index.read(false);
+        }
+
+        let prompt = crate::render_prompt(
+            &cli,
+            &environment,
+            side,
+            dir_path.clone(),
+            repository.as_ref(),
+        );
+        if let Some(mut stream) = stream {
+            let _ = stream.write_all(format!("0{}", prompt).as_bytes());
+        }
+        mailbox.send(Message::Answered {
+            request,
+            directory: dir_path,
+            repository,
+        });
+    });
+}
+
+// So much of the prompt depends on the environment (config overrides, SSH, NO_COLOR, the
+// terminal) that it's worked out in the client's.
+#[cfg(unix)]
+fn parse(request: &Request) -> Result<(Cli, Option<Side>, Environment, PathBuf), String> {
+    if request.version != env!("CARGO_PKG_VERSION") {
+        return Err("the client is a different version".to_string());
+    }
+
+    let environment = request.environment();
+    let cli = Cli::try_parse_from(&request.arguments).map_err(|error| error.to_string())?;
+    let side = match cli.command {
+        None => None,
+        Some(Command::Prompt { side, .. }) | Some(Command::Query { side, .. }) => side,
+        Some(_) => return Err("only prompts can be queried".to_string()),
+    };
+    let dir_path = crate::prompt_path(&cli, &environment).ok_or("there's no working directory")?;
+
+    Ok((cli, side, environment, dir_path))
+}
+
+// With no daemon to ask, or one that can't answer in time, the prompt is worked out here as
+// usual.
+#[cfg(unix)]
+pub fn query(cli: &Cli, environment: &Environment, side: Option<Side>, socket: Option<&Path>) {
+    let timeout = Config::load(&crate::load_options(cli, environment), None)
+        .timeout_ms
+        .map_or(ANSWER_TIMEOUT, |timeout_ms| {
+            Duration::from_millis(timeout_ms) + ANSWER_MARGIN
+        });
+
+    match ask(environment, socket, timeout) {
+        Some(prompt) => println!("{}", prompt),
+        None => crate::print_prompt(cli, environment, side),
+    }
+}
+
+#[cfg(not(unix))]
+pub fn query(cli: &Cli, environment: &Environment, side: Option<Side>, _socket: Option<&Path>) {
+    crate::print_prompt(cli, environment, side);
+}
+
+#[cfg(unix)]
+fn ask(environment: &Environment, socket: Option<&Path>, timeout: Duration) -> Option<String> {
+    let path = socket.map(Path::to_path_buf).or_else(socket_path)?;
+    let mut stream = UnixStream::connect(path).ok()?;
+    stream.set_write_timeout(Some(CLIENT_TIMEOUT)).ok()?;
+    stream.set_read_timeout(Some(timeout)).ok()?;
+
+    stream
+        .write_all(&Request::new(environment)?.encode())
+        .ok()?;
+    stream.shutdown(Shutdown::Write).ok()?;
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).ok()?;
+    response.strip_prefix('0').map(str::to_string)
+}
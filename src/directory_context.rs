@@ -0,0 +1,452 @@
+use std::path::{Component, Path, PathBuf, MAIN_SEPARATOR, MAIN_SEPARATOR_STR};
+
+use git2::Repository;
+
+use crate::cli::Keymap;
+use crate::config::{Config, PathStyle};
+use crate::environment::Environment;
+use crate::text::truncate_start;
+
+pub struct DirectoryContext<'a> {
+    pub path: PathBuf,
+    pub repository: Option<&'a Repository>,
+    pub config: &'a Config,
+    pub environment: &'a Environment,
+    // What the shell told us about the command that just finished.
+    pub last_exit_code: Option<i32>,
+    pub command_duration_ms: Option<u64>,
+    pub keymap: Option<Keymap>,
+}
+
+impl<'a> DirectoryContext<'a> {
+    fn current_directory_short_name(&self) -> Option<String> {
+        self.directory_short_name(&self.path)
+    }
+
+    // A root like `/` or a drive like `C:\` has no name of its own, so it's shown whole.
+    fn directory_short_name(&self, path: &Path) -> Option<String> {
+        if path.is_dir() {
+            match path.file_name() {
+                Some(name_os_str) => name_os_str.to_str().map(|name| name.to_string()),
+                None => path
+                    .to_str()
+                    .map(|root| strip_verbatim_prefix(root).to_string()),
+            }
+        } else {
+            None
+        }
+    }
+
+    fn format_subdirectory_path(
+        &self,
+        repository_path: Option<&Path>,
+        current_working_directory: &Path,
+    ) -> Option<String> {
+        match repository_path {
+            Some(repository_path) => {
+                let repository_path_buf = repository_path.to_path_buf();
+                let diff = current_working_directory.strip_prefix(&repository_path_buf);
+                let root_name_path = diff
+                    .as_ref()
+                    .ok()
+                    .and_then(|diff_path| self.logical_ancestor(diff_path, repository_path))
+                    .unwrap_or(repository_path);
+
+                match self.directory_short_name(root_name_path) {
+                    Some(short_name) => {
+                        let mut result = String::new();
+                        result.push_str(&short_name);
+                        if !result.ends_with(MAIN_SEPARATOR) {
+                            result.push(MAIN_SEPARATOR);
+                        }
+
+                        if let Some(diff_path_str) =
+                            diff.ok().and_then(|diff_path| diff_path.to_str())
+                        {
+                            result.push_str(diff_path_str);
+                        }
+
+                        Some(result)
+                    }
+                    None => None,
+                }
+            }
+            None => None,
+        }
+    }
+
+    // The ancestor of our (possibly symlinked) path that corresponds to the repository root,
+    // so the root is named the way the user reached it.
+    fn logical_ancestor(&self, diff_path: &Path, repository_path: &Path) -> Option<&Path> {
+        self.path
+            .ancestors()
+            .nth(diff_path.components().count())
+            .filter(|ancestor| ancestor.canonicalize().ok().as_deref() == Some(repository_path))
+    }
+
+    // The `~` or `~name` to show for the start of the path, and the rest of it. The longest of the
+    // home directory and the named directories that the path is in wins, as in zsh. A named
+    // directory can be given from the home directory, like `~/code`.
+    fn home_abbreviation(&self) -> Option<(String, &Path)> {
+        let home = self
+            .environment
+            .var_os("HOME")
+            .filter(|home| !home.is_empty())
+            .map(PathBuf::from);
+        let named_directories = self
+            .config
+            .named_directories
+            .iter()
+            .map(|(name, directory)| {
+                let directory = match (directory.strip_prefix("~"), &home) {
+                    (Ok(rest), Some(home)) => home.join(rest),
+                    _ => directory.clone(),
+                };
+                (format!("~{}", name), directory)
+            });
+
+        named_directories
+            .chain(home.clone().map(|home| ("~".to_string(), home)))
+            .filter_map(|(prefix, directory)| {
+                let rest = self.path.strip_prefix(&directory).ok()?;
+                Some((directory.components().count(), prefix, rest))
+            })
+            .max_by_key(|(length, _, _)| *length)
+            .map(|(_, prefix, rest)| (prefix, rest))
+    }
+
+    fn full_path(&self) -> Option<String> {
+        match self.home_abbreviation() {
+            Some((prefix, rest)) if rest.as_os_str().is_empty() => Some(prefix),
+            Some((prefix, rest)) => Some(format!("{}{}{}", prefix, MAIN_SEPARATOR, rest.to_str()?)),
+            None => self
+                .path
+                .to_str()
+                .map(|path| strip_verbatim_prefix(path).to_string()),
+        }
+    }
+
+    // Counting `~` and `~name` as one component, and a root as one too, so that it's kept when
+    // there aren't enough others.
+    fn trailing_path(&self, count: usize) -> Option<String> {
+        let full_path = self.full_path()?;
+        let start = full_path
+            .rmatch_indices(MAIN_SEPARATOR)
+            .nth(count.saturating_sub(1))
+            .map(|(index, _)| index + MAIN_SEPARATOR.len_utf8())
+            .filter(|start| *start < full_path.len());
+
+        match start {
+            Some(start) => Some(full_path[start..].to_string()),
+            None => Some(full_path),
+        }
+    }
+
+    // Like fish's `prompt_pwd`: the directories on the way shortened to their first few
+    // characters, except for the repository's root and the directory itself.
+    fn fish_path(&self, length: usize) -> Option<String> {
+        let (start, rest) = match self.home_abbreviation() {
+            Some((prefix, rest)) => (prefix, rest),
+            None => {
+                let root: PathBuf = self
+                    .path
+                    .components()
+                    .take_while(|component| !matches!(component, Component::Normal(_)))
+                    .collect();
+                let rest = self.path.strip_prefix(&root).unwrap_or(&self.path);
+                (strip_verbatim_prefix(root.to_str()?).to_string(), rest)
+            }
+        };
+        let names = rest
+            .components()
+            .filter_map(|component| match component {
+                Component::Normal(name) => Some(name.to_str()),
+                _ => None,
+            })
+            .collect::<Option<Vec<&str>>>()?;
+
+        let repository_depth = self.repository_depth();
+        let last = names.len().saturating_sub(1);
+        let names: Vec<String> = names
+            .iter()
+            .enumerate()
+            .map(|(index, name)| {
+                if index == last || repository_depth == Some(last - index) {
+                    name.to_string()
+                } else {
+                    abbreviate_directory(name, length)
+                }
+            })
+            .collect();
+
+        let mut result = start;
+        if !names.is_empty() {
+            if !result.ends_with(MAIN_SEPARATOR) {
+                result.push(MAIN_SEPARATOR);
+            }
+            result.push_str(&names.join(MAIN_SEPARATOR_STR));
+        }
+        Some(result)
+    }
+
+    // How many directories up from ours the repository's root is, to find its name at the same
+    // distance from the end of the path.
+    fn repository_depth(&self) -> Option<usize> {
+        let root = self.repository?.workdir()?.canonicalize().ok()?;
+
+        self.path
+            .ancestors()
+            .position(|ancestor| ancestor.canonicalize().ok().as_ref() == Some(&root))
+    }
+
+    // Leaves out directories from the left until the path fits, each run of them replaced with
+    // the ellipsis, but keeps the repository's name and the directory's own for as long as it
+    // can. Only then does the repository's name go, and then the start of whatever is left.
+    fn fit_path(&self, path: String, max_width: usize) -> String {
+        let ellipsis = &self.config.ellipsis_symbol;
+        let parts: Vec<&str> = path.split(MAIN_SEPARATOR).collect();
+        let last = parts.len() - 1;
+        let repository_index = self
+            .repository_depth()
+            .filter(|depth| *depth <= last)
+            .map(|depth| last - depth);
+
+        let render = |kept: &[bool]| {
+            let mut shown: Vec<&str> = Vec::new();
+            for (index, part) in parts.iter().enumerate() {
+                if kept[index] {
+                    shown.push(part);
+                } else if index == 0 || kept[index - 1] {
+                    shown.push(ellipsis);
+                }
+            }
+            shown.join(MAIN_SEPARATOR_STR)
+        };
+
+        let mut kept = vec![true; parts.len()];
+        let mut result = path.clone();
+        let droppable = (0..last)
+            .filter(|index| Some(*index) != repository_index)
+            .chain(repository_index.filter(|index| *index != last));
+        for index in droppable {
+            if result.chars().count() <= max_width {
+                return result;
+            }
+            kept[index] = false;
+            result = render(&kept);
+        }
+
+        truncate_start(&result, max_width, ellipsis)
+    }
+
+    fn path_summary(&self) -> Option<String> {
+        let path = self.styled_path()?;
+
+        match self.config.path_max_width {
+            Some(max_width) => Some(self.fit_path(path, max_width)),
+            None => Some(path),
+        }
+    }
+
+    fn styled_path(&self) -> Option<String> {
+        match self.config.path_style {
+            PathStyle::Repository => self.repository_path(),
+            PathStyle::Basename => self.current_directory_short_name(),
+            PathStyle::Full => self.full_path(),
+            PathStyle::Trailing => self.trailing_path(self.config.path_components),
+            PathStyle::Fish => self.fish_path(self.config.path_abbreviation_length),
+        }
+    }
+
+    fn repository_path(&self) -> Option<String> {
+        match self.repository {
+            Some(repository) => {
+                let repository_root = if repository.is_bare() {
+                    Some(repository.path())
+                } else {
+                    repository.workdir()
+                };
+
+                // The repository root comes back from libgit2 with symlinks resolved, while the
+                // path we were given may be a logical one, so compare the physical locations.
+                let physical_path = self
+                    .path
+                    .canonicalize()
+                    .unwrap_or_else(|_| self.path.clone());
+                let repository_root = repository_root
+                    .map(|root| root.canonicalize().unwrap_or_else(|_| root.to_path_buf()));
+                let repository_root = repository_root.as_deref();
+
+                if self.paths_match(repository_root, &physical_path) {
+                    self.current_directory_short_name()
+                } else {
+                    self.format_subdirectory_path(repository_root, &physical_path)
+                }
+            }
+            None => self.current_directory_short_name(),
+        }
+    }
+
+    fn paths_match(
+        &self,
+        repository_path: Option<&Path>,
+        current_working_directory: &Path,
+    ) -> bool {
+        match repository_path {
+            Some(repository_path) => repository_path == current_working_directory,
+            None => false,
+        }
+    }
+}
+
+// A hidden directory keeps its dot, so `.config` is `.c` rather than `.`.
+fn abbreviate_directory(name: &str, length: usize) -> String {
+    let (dot, name) = match name.strip_prefix('.') {
+        Some(name) => (".", name),
+        None => ("", name),
+    };
+
+    format!("{}{}", dot, name.chars().take(length).collect::<String>())
+}
+
+// On Windows, canonicalizing gives paths like `\\?\C:\Users`, which nobody wants to read.
+fn strip_verbatim_prefix(path: &str) -> &str {
+    path.strip_prefix(r"\\?\").unwrap_or(path)
+}
+
+impl<'a> std::fmt::Display for DirectoryContext<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        match self.path_summary() {
+            Some(name) => write!(f, "{}", name),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::process;
+
+    use super::*;
+
+    // A home directory with a repository at `~/work/projects/repo`, and `src/deep` inside it.
+    fn home(name: &str) -> (PathBuf, Repository) {
+        let home = std::env::temp_dir().join(format!("mfzgp-{}-{}", process::id(), name));
+        let _ = fs::remove_dir_all(&home);
+        let workdir = home.join("work/projects/repo");
+        fs::create_dir_all(workdir.join("src/deep")).unwrap();
+        let repository = Repository::init(&workdir).unwrap();
+
+        (home, repository)
+    }
+
+    fn summary(
+        home: &Path,
+        path: &Path,
+        repository: Option<&Repository>,
+        config: &Config,
+    ) -> String {
+        let environment = Environment::new([("HOME".into(), home.into())], None);
+        let dir = DirectoryContext {
+            path: path.to_path_buf(),
+            repository,
+            config,
+            environment: &environment,
+            last_exit_code: None,
+            command_duration_ms: None,
+            keymap: None,
+        };
+        dir.to_string()
+    }
+
+    fn fitted(max_width: usize, with_repository: bool) -> String {
+        let (home, repository) = home(&format!("fit-{}-{}", max_width, with_repository));
+        let config = Config {
+            path_style: PathStyle::Full,
+            path_max_width: Some(max_width),
+            ..Config::default()
+        };
+        let path = home.join("work/projects/repo/src/deep");
+
+        summary(
+            &home,
+            &path,
+            Some(&repository).filter(|_| with_repository),
+            &config,
+        )
+    }
+
+    #[test]
+    fn a_path_that_fits_is_left_alone() {
+        assert_eq!(fitted(40, true), "~/work/projects/repo/src/deep");
+        assert_eq!(fitted(29, true), "~/work/projects/repo/src/deep");
+    }
+
+    #[test]
+    fn directories_are_left_out_from_the_left() {
+        assert_eq!(fitted(25, true), "…/projects/repo/src/deep");
+        assert_eq!(fitted(14, false), "…/src/deep");
+    }
+
+    #[test]
+    fn the_repository_name_is_kept_between_runs_of_left_out_directories() {
+        assert_eq!(fitted(14, true), "…/repo/…/deep");
+    }
+
+    #[test]
+    fn the_repository_name_goes_when_nothing_else_is_left() {
+        assert_eq!(fitted(8, true), "…/deep");
+    }
+
+    #[test]
+    fn the_directory_itself_loses_its_start_when_even_it_is_too_wide() {
+        assert_eq!(fitted(4, true), "…eep");
+    }
+
+    fn fish(path: &str, with_repository: bool) -> String {
+        let (home, repository) = home(&format!("fish-{}", path.replace('/', "-")));
+        fs::create_dir_all(home.join(path)).unwrap();
+        let config = Config {
+            path_style: PathStyle::Fish,
+            path_abbreviation_length: 1,
+            ..Config::default()
+        };
+
+        summary(
+            &home,
+            &home.join(path),
+            Some(&repository).filter(|_| with_repository),
+            &config,
+        )
+    }
+
+    #[test]
+    fn fish_paths_shorten_the_directories_on_the_way() {
+        assert_eq!(fish("work/projects/repo/src/deep", false), "~/w/p/r/s/deep");
+    }
+
+    #[test]
+    fn fish_paths_keep_the_repository_name_whole() {
+        assert_eq!(
+            fish("work/projects/repo/src/deep", true),
+            "~/w/p/repo/s/deep"
+        );
+        assert_eq!(fish("work/projects/repo", true), "~/w/p/repo");
+    }
+
+    #[test]
+    fn fish_paths_keep_the_dot_of_hidden_directories() {
+        assert_eq!(fish(".config/nvim", false), "~/.c/nvim");
+        assert_eq!(fish(".config/.hidden", false), "~/.c/.hidden");
+    }
+
+    #[test]
+    fn abbreviates_directories() {
+        assert_eq!(abbreviate_directory("projects", 1), "p");
+        assert_eq!(abbreviate_directory("projects", 3), "pro");
+        assert_eq!(abbreviate_directory("src", 5), "src");
+        assert_eq!(abbreviate_directory(".config", 2), ".co");
+        assert_eq!(abbreviate_directory("école", 2), "éc");
+    }
+}
@@ -0,0 +1,52 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::environment::Environment;
+
+// `GIT_CEILING_DIRECTORIES`, along with any from the config.
+pub fn ceiling_directories(configured: &[PathBuf], environment: &Environment) -> Vec<PathBuf> {
+    let mut ceilings = configured.to_vec();
+
+    if let Some(value) = environment.var_os("GIT_CEILING_DIRECTORIES") {
+        ceilings.extend(env::split_paths(&value).filter(|path| path.is_absolute()));
+    }
+
+    ceilings
+}
+
+// Looks for the git directory the way git does, checking each directory up from `path` for
+// `.git`, or for being a git directory itself, and never going up into a ceiling directory.
+// Only the file system is touched, so not being in a repository at all, the usual case in `$HOME`,
+// costs a few `stat`s.
+pub fn find_git_dir(path: &Path, ceilings: &[PathBuf]) -> Option<PathBuf> {
+    for dir in path.ancestors() {
+        if dir != path && ceilings.iter().any(|ceiling| ceiling == dir) {
+            return None;
+        }
+
+        let dot_git = dir.join(".git");
+
+        if dot_git.is_dir() {
+            return Some(dot_git);
+        }
+
+        // In a linked worktree or a submodule, `.git` is a file pointing elsewhere.
+        if dot_git.is_file() {
+            let contents = fs::read_to_string(&dot_git).ok()?;
+            let target = contents.strip_prefix("gitdir:")?.trim();
+            return Some(dir.join(target));
+        }
+
+        if is_git_dir(dir) {
+            return Some(dir.to_path_buf());
+        }
+    }
+
+    None
+}
+
+// Inside a bare repository, or inside `.git`.
+fn is_git_dir(dir: &Path) -> bool {
+    dir.join("HEAD").is_file() && dir.join("objects").is_dir() && dir.join("refs").is_dir()
+}
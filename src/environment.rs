@@ -0,0 +1,66 @@
+use std::collections::HashMap;
+use std::env;
+use std::ffi::{OsStr, OsString};
+use std::path::{Path, PathBuf};
+
+// The variables and working directory a prompt is worked out for. Usually they're this process's
+// own, but the daemon answers for its clients, and one client's mustn't leak into another's
+// prompt, or into the daemon's own lookups that are still running.
+#[derive(Clone)]
+pub struct Environment {
+    variables: HashMap<OsString, OsString>,
+    directory: Option<PathBuf>,
+}
+
+impl Environment {
+    pub fn new(
+        variables: impl IntoIterator<Item = (OsString, OsString)>,
+        directory: Option<PathBuf>,
+    ) -> Self {
+        Environment {
+            variables: variables.into_iter().collect(),
+            directory,
+        }
+    }
+
+    pub fn current() -> Self {
+        Environment::new(env::vars_os(), env::current_dir().ok())
+    }
+
+    // Names are case-insensitive on Windows.
+    pub fn var_os(&self, name: &str) -> Option<&OsStr> {
+        if let Some(value) = self.variables.get(OsStr::new(name)) {
+            return Some(value);
+        }
+
+        if cfg!(windows) {
+            return self
+                .variables
+                .iter()
+                .find(|(key, _)| {
+                    key.to_str()
+                        .is_some_and(|key| key.eq_ignore_ascii_case(name))
+                })
+                .map(|(_, value)| value.as_os_str());
+        }
+
+        None
+    }
+
+    // Unset, empty and not unicode all come to the same thing.
+    pub fn var(&self, name: &str) -> Option<&str> {
+        self.var_os(name)?
+            .to_str()
+            .filter(|value| !value.is_empty())
+    }
+
+    pub fn vars_os(&self) -> impl Iterator<Item = (&OsStr, &OsStr)> {
+        self.variables
+            .iter()
+            .map(|(key, value)| (key.as_os_str(), value.as_os_str()))
+    }
+
+    pub fn current_dir(&self) -> Option<&Path> {
+        self.directory.as_deref()
+    }
+}
@@ -0,0 +1,475 @@
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use git2::{
+    BranchType, ConfigLevel, DescribeFormatOptions, DescribeOptions, Diff, DiffOptions,
+    IndexEntryExtendedFlag, IndexEntryFlag, ObjectType, Oid, Reference, Repository, Status,
+    StatusOptions, SubmoduleIgnore, SubmoduleStatus,
+};
+
+use crate::operation;
+use crate::text::abbreviate_sha;
+
+// Both diffs cover the whole work tree, or only what's beneath `pathspec`.
+fn diff_options(pathspec: Option<&Path>) -> DiffOptions {
+    let mut options = DiffOptions::new();
+    if let Some(pathspec) = pathspec {
+        options.pathspec(pathspec);
+    }
+    options
+}
+
+pub fn staged_diff<'repo>(
+    repository: &'repo Repository,
+    pathspec: Option<&Path>,
+) -> Option<Diff<'repo>> {
+    let head_tree = repository
+        .head()
+        .and_then(|head_reference| head_reference.peel_to_tree())
+        .ok();
+
+    repository
+        .diff_tree_to_index(head_tree.as_ref(), None, Some(&mut diff_options(pathspec)))
+        .ok()
+}
+
+pub fn unstaged_diff<'repo>(
+    repository: &'repo Repository,
+    pathspec: Option<&Path>,
+) -> Option<Diff<'repo>> {
+    repository
+        .diff_index_to_workdir(None, Some(&mut diff_options(pathspec)))
+        .ok()
+}
+
+pub fn line_counts(diffs: &[&Option<Diff>]) -> (usize, usize) {
+    diffs
+        .iter()
+        .filter_map(|diff| diff.as_ref().and_then(|diff| diff.stats().ok()))
+        .fold((0, 0), |(insertions, deletions), stats| {
+            (
+                insertions + stats.insertions(),
+                deletions + stats.deletions(),
+            )
+        })
+}
+
+pub fn shows_untracked_files(repository: &Repository) -> bool {
+    repository
+        .config()
+        .and_then(|config| config.get_string("status.showUntrackedFiles"))
+        .map_or(true, |value| value != "no")
+}
+
+// `core.fsmonitor` is either a boolean, for git's built-in daemon, or the path to a hook like
+// Watchman's.
+pub fn uses_fsmonitor(repository: &Repository) -> bool {
+    repository
+        .config()
+        .and_then(|config| config.get_string("core.fsmonitor"))
+        .is_ok_and(|value| {
+            !matches!(
+                value.to_lowercase().as_str(),
+                "" | "false" | "no" | "off" | "0"
+            )
+        })
+}
+
+pub fn count_index_entries(repository: &Repository) -> usize {
+    repository.index().map_or(0, |index| index.len())
+}
+
+// File counts from a single status pass over the index and the work tree.
+#[derive(Default)]
+pub struct StatusCounts {
+    pub staged: usize,
+    pub unstaged: usize,
+    pub untracked: usize,
+    pub conflicted: usize,
+    pub deleted: usize,
+    pub renamed: usize,
+}
+
+// Ignored files are never walked. Untracked files are only looked for when asked, and renames
+// only detected when asked, since both cost time in a big work tree. Each file is counted once:
+// a conflicted file only as conflicted, and a deleted or renamed one only as that, not as staged
+// or unstaged too.
+pub fn count_statuses(
+    repository: &Repository,
+    include_untracked: bool,
+    find_renames: bool,
+    pathspec: Option<&Path>,
+) -> StatusCounts {
+    let mut options = StatusOptions::new();
+    options
+        .include_ignored(false)
+        .include_untracked(include_untracked)
+        .recurse_untracked_dirs(include_untracked)
+        .renames_head_to_index(find_renames)
+        .renames_index_to_workdir(find_renames);
+    if let Some(pathspec) = pathspec {
+        options.pathspec(pathspec);
+    }
+
+    let mut counts = StatusCounts::default();
+    let statuses = match repository.statuses(Some(&mut options)) {
+        Ok(statuses) => statuses,
+        Err(_) => return counts,
+    };
+
+    let staged = Status::INDEX_NEW | Status::INDEX_MODIFIED | Status::INDEX_TYPECHANGE;
+    let unstaged = Status::WT_MODIFIED | Status::WT_TYPECHANGE;
+
+    for entry in statuses.iter() {
+        let status = entry.status();
+        if status.is_conflicted() {
+            counts.conflicted += 1;
+            continue;
+        }
+        if status.intersects(Status::INDEX_DELETED | Status::WT_DELETED) {
+            counts.deleted += 1;
+            continue;
+        }
+        if status.intersects(Status::INDEX_RENAMED | Status::WT_RENAMED) {
+            counts.renamed += 1;
+            continue;
+        }
+
+        counts.staged += status.intersects(staged) as usize;
+        counts.unstaged += status.intersects(unstaged) as usize;
+        counts.untracked += status.is_wt_new() as usize;
+    }
+
+    counts
+}
+
+// Each stash is an entry in the reflog of refs/stash.
+pub fn count_stashes(repository: &Repository) -> usize {
+    repository
+        .reflog("refs/stash")
+        .map_or(0, |reflog| reflog.len())
+}
+
+pub fn any_submodules_dirty(repository: &Repository) -> bool {
+    let dirty = SubmoduleStatus::INDEX_MODIFIED
+        | SubmoduleStatus::WD_MODIFIED
+        | SubmoduleStatus::WD_INDEX_MODIFIED
+        | SubmoduleStatus::WD_WD_MODIFIED
+        | SubmoduleStatus::WD_UNTRACKED;
+
+    repository.submodules().is_ok_and(|submodules| {
+        submodules.iter().any(|submodule| {
+            submodule
+                .name()
+                .and_then(|name| {
+                    repository
+                        .submodule_status(name, SubmoduleIgnore::None)
+                        .ok()
+                })
+                .is_some_and(|status| status.intersects(dirty))
+        })
+    })
+}
+
+// libgit2 doesn't read `config.worktree`, which is where `git sparse-checkout`
+// writes its settings, so look there before falling back to the usual config.
+fn git_config_bool(repository: &Repository, name: &str) -> Option<bool> {
+    let worktree_config_path = repository.path().join("config.worktree");
+    if worktree_config_path.is_file() {
+        let value = git2::Config::open(&worktree_config_path)
+            .and_then(|worktree_config| worktree_config.get_bool(name));
+        if let Ok(value) = value {
+            return Some(value);
+        }
+    }
+
+    repository
+        .config()
+        .and_then(|config| config.get_bool(name))
+        .ok()
+}
+
+pub fn is_sparse_checkout(repository: &Repository) -> bool {
+    git_config_bool(repository, "core.sparseCheckout").unwrap_or(false)
+}
+
+// A sparse index keeps each directory outside the sparse-checkout cone as a single entry. libgit2
+// can't read one at all, and `git` only keeps it sparse for the commands that know how.
+pub fn uses_sparse_index(repository: &Repository) -> bool {
+    git_config_bool(repository, "index.sparse").unwrap_or(false)
+}
+
+pub fn identity_needs_attention(repository: &Repository) -> bool {
+    let config = match repository.config() {
+        Ok(config) => config,
+        Err(_) => return false,
+    };
+
+    if config.get_string("user.email").is_err() {
+        return true;
+    }
+
+    let local_email = config
+        .open_level(ConfigLevel::Local)
+        .and_then(|local_config| local_config.get_string("user.email"));
+    let global_email = config
+        .open_level(ConfigLevel::Global)
+        .and_then(|global_config| global_config.get_string("user.email"));
+
+    match (local_email, global_email) {
+        (Ok(local_email), Ok(global_email)) => local_email != global_email,
+        _ => false,
+    }
+}
+
+pub fn any_skipped_index_entries(repository: &Repository) -> bool {
+    let index = match repository.index() {
+        Ok(index) => index,
+        Err(_) => return false,
+    };
+
+    // Sparse checkouts mark everything outside the cone as skip-worktree,
+    // which is already covered by the sparse marker.
+    let check_skip_worktree = !is_sparse_checkout(repository);
+
+    index.iter().any(|entry| {
+        let assume_unchanged = IndexEntryFlag::from_bits_truncate(entry.flags).is_valid();
+        let skip_worktree =
+            IndexEntryExtendedFlag::from_bits_truncate(entry.flags_extended).is_skip_worktree();
+
+        assume_unchanged || (check_skip_worktree && skip_worktree)
+    })
+}
+
+pub enum Signing {
+    Disabled,
+    Enabled,
+    MissingKey,
+}
+
+pub fn commit_signing(repository: &Repository) -> Signing {
+    let config = match repository.config() {
+        Ok(config) => config,
+        Err(_) => return Signing::Disabled,
+    };
+
+    if !config.get_bool("commit.gpgsign").unwrap_or(false) {
+        return Signing::Disabled;
+    }
+
+    let signing_key = match config.get_path("user.signingkey") {
+        Ok(signing_key) => signing_key,
+        Err(_) => return Signing::MissingKey,
+    };
+
+    // SSH signing keys are files, so we can cheaply check that they exist.
+    // OpenPGP keys live in the keyring, which is too slow to ask on every prompt.
+    let is_ssh = config
+        .get_string("gpg.format")
+        .is_ok_and(|format| format == "ssh");
+    let is_key_literal = signing_key
+        .to_str()
+        .is_some_and(|signing_key| signing_key.starts_with("key::"));
+
+    if is_ssh && !is_key_literal && !signing_key.exists() {
+        Signing::MissingKey
+    } else {
+        Signing::Enabled
+    }
+}
+
+pub fn ahead_behind(repository: &Repository, branch_name: &str) -> Option<(usize, usize)> {
+    let branch = repository
+        .find_branch(branch_name, BranchType::Local)
+        .ok()?;
+    let upstream = branch.upstream().ok()?;
+    let local_oid = branch.get().target()?;
+    let upstream_oid = upstream.get().target()?;
+
+    repository.graph_ahead_behind(local_oid, upstream_oid).ok()
+}
+
+pub struct Upstream {
+    pub name: String,
+    pub is_gone: bool,
+}
+
+pub fn upstream(repository: &Repository, branch_name: &str) -> Option<Upstream> {
+    let refname = format!("refs/heads/{}", branch_name);
+    let upstream_buf = repository.branch_upstream_name(&refname).ok()?;
+    let upstream_refname = upstream_buf.as_str()?;
+
+    let name = upstream_refname
+        .strip_prefix("refs/remotes/")
+        .or_else(|| upstream_refname.strip_prefix("refs/heads/"))
+        .unwrap_or(upstream_refname)
+        .to_string();
+
+    Some(Upstream {
+        name,
+        is_gone: repository.find_reference(upstream_refname).is_err(),
+    })
+}
+
+// Until a remote has been fetched there's nothing to hide, and the walk would count the whole
+// history, so that's left unknown too.
+pub fn count_unpushed_commits(repository: &Repository) -> Option<usize> {
+    if repository.remotes().ok()?.is_empty() {
+        return None;
+    }
+    repository
+        .references_glob("refs/remotes/*")
+        .ok()?
+        .next()?
+        .ok()?;
+
+    let mut revwalk = repository.revwalk().ok()?;
+    revwalk.push_glob("refs/heads/*").ok()?;
+    revwalk.hide_glob("refs/remotes/*").ok()?;
+
+    Some(revwalk.count())
+}
+
+pub struct DefaultBranch {
+    pub name: String,
+    pub behind: usize,
+}
+
+pub fn behind_default_branch(repository: &Repository) -> Option<DefaultBranch> {
+    let origin_head = repository.find_reference("refs/remotes/origin/HEAD").ok()?;
+    let default_refname = origin_head.symbolic_target()?.to_string();
+    let default_oid = repository.refname_to_id(&default_refname).ok()?;
+    let head_oid = repository.head().ok()?.target()?;
+
+    let (_, behind) = repository.graph_ahead_behind(head_oid, default_oid).ok()?;
+    let name = default_refname
+        .strip_prefix("refs/remotes/origin/")
+        .unwrap_or(&default_refname)
+        .to_string();
+
+    Some(DefaultBranch { name, behind })
+}
+
+pub fn commit_age_seconds(repository: &Repository) -> Option<u64> {
+    let commit = repository.head().ok()?.peel_to_commit().ok()?;
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).ok()?.as_secs() as i64;
+
+    Some((now - commit.time().seconds()).max(0) as u64)
+}
+
+pub fn head_commit_subject(repository: &Repository) -> Option<String> {
+    let commit = repository.head().ok()?.peel_to_commit().ok()?;
+    commit.summary().map(|summary| summary.to_string())
+}
+
+// Compares what the tags point at rather than peeling every one of them. A lightweight tag points
+// at the commit itself, and a packed annotated one remembers the commit it peels to, so only a
+// loose annotated tag has to be read, after its header says that's what it is. The first name
+// wins when there are several, as with `git tag`.
+pub fn head_tag(repository: &Repository) -> Option<String> {
+    let head_oid = repository.head().ok()?.peel_to_commit().ok()?.id();
+    let odb = repository.odb().ok()?;
+
+    let points_at_head = |reference: &Reference| match (reference.target(), reference.target_peel())
+    {
+        (Some(target), _) if target == head_oid => true,
+        (_, Some(peeled)) => peeled == head_oid,
+        (Some(target), None) => {
+            odb.read_header(target)
+                .is_ok_and(|(_, kind)| kind == ObjectType::Tag)
+                && repository
+                    .find_tag(target)
+                    .is_ok_and(|tag| tag.target_id() == head_oid)
+        }
+        (None, None) => false,
+    };
+
+    repository
+        .references_glob("refs/tags/*")
+        .ok()?
+        .flatten()
+        .filter(points_at_head)
+        .filter_map(|reference| reference.shorthand().map(str::to_string))
+        .min()
+}
+
+pub fn unborn_branch_name(repository: &Repository) -> Option<String> {
+    let head_target = repository
+        .find_reference("HEAD")
+        .ok()
+        .and_then(|head| head.symbolic_target().map(|target| target.to_string()));
+
+    match head_target {
+        Some(target) => Some(
+            target
+                .strip_prefix("refs/heads/")
+                .unwrap_or(&target)
+                .to_string(),
+        ),
+        None => repository
+            .config()
+            .and_then(|config| config.get_string("init.defaultBranch"))
+            .ok(),
+    }
+}
+
+pub fn describe_head(repository: &Repository, sha_length: usize) -> Option<String> {
+    let mut describe_options = DescribeOptions::new();
+    describe_options.describe_tags();
+
+    let mut format_options = DescribeFormatOptions::new();
+    format_options.abbreviated_size(sha_length as u32);
+
+    repository
+        .describe(&describe_options)
+        .and_then(|describe| describe.format(Some(&format_options)))
+        .ok()
+}
+
+pub fn describe_cherry_pick(repository: &Repository, sha_length: usize) -> Option<String> {
+    let sha = operation::cherry_pick_head(repository.path())?;
+    let short_sha = abbreviate_sha(&sha, sha_length);
+
+    let summary = Oid::from_str(&sha)
+        .and_then(|oid| repository.find_commit(oid))
+        .ok()
+        .and_then(|commit| commit.summary().map(|summary| summary.to_string()));
+
+    match summary {
+        Some(summary) => Some(format!("{} {}", short_sha, summary)),
+        None => Some(short_sha.to_string()),
+    }
+}
+
+pub fn bisect_steps_left(repository: &Repository) -> Option<usize> {
+    let bad = repository.refname_to_id("refs/bisect/bad").ok()?;
+
+    let mut revwalk = repository.revwalk().ok()?;
+    revwalk.push(bad).ok()?;
+
+    for reference in repository.references_glob("refs/bisect/good-*").ok()? {
+        if let Some(good) = reference.ok().and_then(|reference| reference.target()) {
+            revwalk.hide(good).ok()?;
+        }
+    }
+
+    Some(estimate_bisect_steps(revwalk.count()))
+}
+
+// Mirrors git's own estimate in bisect.c, so the number matches what
+// `git bisect` printed after the last step.
+fn estimate_bisect_steps(all: usize) -> usize {
+    if all < 3 {
+        return 0;
+    }
+
+    let n = (usize::BITS - 1 - all.leading_zeros()) as usize;
+    let e = 1 << n;
+    let x = all - e;
+
+    if e < 3 * x {
+        n
+    } else {
+        n - 1
+    }
+}
@@ -0,0 +1,26 @@
+use std::fs;
+use std::path::Path;
+
+use crate::text::abbreviate_sha;
+
+// What HEAD says, straight from the file in `git_dir`: the branch name, or the abbreviated sha
+// when detached. Anything else means HEAD is broken, and is left for git to report.
+pub fn branch(git_dir: &Path, sha_length: usize, detached_symbol: &str) -> Option<String> {
+    let head = fs::read_to_string(git_dir.join("HEAD")).ok()?;
+    let head = head.trim();
+
+    match head.strip_prefix("ref: ") {
+        Some(reference) => Some(
+            reference
+                .strip_prefix("refs/heads/")
+                .unwrap_or(reference)
+                .to_string(),
+        ),
+        None if head.is_empty() || !head.bytes().all(|byte| byte.is_ascii_hexdigit()) => None,
+        None => Some(format!(
+            "{} {}",
+            detached_symbol,
+            abbreviate_sha(head, sha_length)
+        )),
+    }
+}
@@ -0,0 +1,78 @@
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::Path;
+
+use git2::{AttrCheckFlags, Index, Repository};
+
+const POINTER_PREFIX: &[u8] = b"version https://git-lfs.github.com/spec/v1";
+
+fn is_non_empty_dir(path: &Path) -> bool {
+    fs::read_dir(path).is_ok_and(|mut entries| entries.next().is_some())
+}
+
+fn is_pointer_file(path: &Path) -> bool {
+    let mut buffer = [0; POINTER_PREFIX.len()];
+
+    File::open(path)
+        .and_then(|mut file| file.read_exact(&mut buffer))
+        .is_ok_and(|_| buffer == POINTER_PREFIX)
+}
+
+fn mentions_lfs(path: &Path) -> bool {
+    fs::read_to_string(path).is_ok_and(|attributes| attributes.contains("filter=lfs"))
+}
+
+// `git lfs track` adds a `filter=lfs` line to a `.gitattributes` file, so when none of them has
+// one, there's no need to look up the attributes of every file in the index.
+fn tracks_lfs_files(repository: &Repository, workdir: &Path, index: &Index) -> bool {
+    mentions_lfs(&repository.path().join("info").join("attributes"))
+        || index.iter().any(|entry| {
+            let is_attributes =
+                entry.path == b".gitattributes" || entry.path.ends_with(b"/.gitattributes");
+
+            is_attributes
+                && std::str::from_utf8(&entry.path)
+                    .is_ok_and(|path| mentions_lfs(&workdir.join(path)))
+        })
+}
+
+fn any_unsmudged_pointers(repository: &Repository, workdir: &Path) -> bool {
+    let index = match repository.index() {
+        Ok(index) => index,
+        Err(_) => return false,
+    };
+    if !tracks_lfs_files(repository, workdir, &index) {
+        return false;
+    }
+
+    index.iter().any(|entry| {
+        let path = match std::str::from_utf8(&entry.path) {
+            Ok(path) => Path::new(path),
+            Err(_) => return false,
+        };
+
+        let is_lfs_tracked = repository
+            .get_attr(path, "filter", AttrCheckFlags::default())
+            .is_ok_and(|filter| filter == Some("lfs"));
+
+        is_lfs_tracked && is_pointer_file(&workdir.join(path))
+    })
+}
+
+// True when LFS files in the working tree are still pointers, or when
+// git-lfs has transfers that haven't finished yet.
+pub fn has_pending_objects(repository: &Repository) -> bool {
+    let lfs_dir = repository.path().join("lfs");
+    if !lfs_dir.is_dir() {
+        return false;
+    }
+
+    if is_non_empty_dir(&lfs_dir.join("incomplete")) || is_non_empty_dir(&lfs_dir.join("tmp")) {
+        return true;
+    }
+
+    match repository.workdir() {
+        Some(workdir) => any_unsmudged_pointers(repository, workdir),
+        None => false,
+    }
+}
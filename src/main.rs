@@ -1,292 +1,1015 @@
-use std::env::current_dir;
-use std::path::PathBuf;
-
-use git2::{Repository, RepositoryState};
-
-struct ZshOutput {
-    is_bold: bool,
-    color: Option<String>,
-    text: String,
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use clap::Parser;
+use git2::Repository;
+
+mod cache;
+mod cli;
+mod clock;
+mod completions;
+mod config;
+mod daemon;
+mod directory_context;
+mod discovery;
+mod environment;
+mod git;
+mod head_file;
+mod json;
+mod lfs;
+mod operation;
+mod porcelain;
+mod remote;
+mod rules;
+mod runtime_dir;
+mod segment;
+mod session;
+mod summary;
+mod symbols;
+mod template;
+mod text;
+mod themes;
+mod timings;
+#[cfg(unix)]
+mod watcher;
+mod zsh_output;
+
+use cli::{Cli, Command, Format, Keymap, Shell, Side};
+use config::{Config, LoadOptions};
+use directory_context::DirectoryContext;
+use environment::Environment;
+use json::Json;
+use segment::{Segment, SegmentKind};
+use template::Template;
+use text::format_duration;
+use zsh_output::{escape, zero_width, Renderer, Target, ZshOutput};
+
+fn not_repo_segment(config: &Config) -> Option<Segment> {
+    let mut output = ZshOutput::new("(not repo)");
+    output.set_color(&config.not_repo_color);
+    output.make_bold();
+    Segment::new(vec![output])
 }
 
-impl ZshOutput {
-    fn new(text: &str) -> Self {
-        ZshOutput {
-            text: text.to_string(),
-            is_bold: false,
-            color: None,
-        }
+fn path_segment(dir: &DirectoryContext) -> Option<Segment> {
+    let path = dir.to_string();
+    if path.is_empty() {
+        return None;
     }
 
-    fn set_color(&mut self, color: &str) {
-        self.color = Some(color.to_string());
+    let mut output = ZshOutput::new(&path);
+    if dir.config.hyperlinks {
+        if let Some(url) = session::file_url(&dir.path, dir.environment) {
+            output.set_link(&url);
+        }
     }
+    Segment::new(vec![output])
+}
 
-    fn make_bold(&mut self) {
-        self.is_bold = true;
-    }
+fn exit_status_segment(dir: &DirectoryContext) -> Option<Segment> {
+    let code = dir.last_exit_code.filter(|code| *code != 0)?;
 
-    fn output(&self) -> String {
-        let mut result = String::new();
+    let mut output = ZshOutput::new(&format!("{} {}", dir.config.exit_status_symbol, code));
+    output.set_color(&dir.config.exit_status_color);
+    Segment::new(vec![output])
+}
 
-        if self.is_bold {
-            result.push_str("%B");
-        }
+// Quick commands aren't worth mentioning.
+fn command_duration_segment(dir: &DirectoryContext) -> Option<Segment> {
+    let duration = dir
+        .command_duration_ms
+        .filter(|duration| *duration >= dir.config.command_duration_threshold_ms)?;
 
-        match self.color {
-            Some(ref c) => {
-                result.push_str("%F{");
-                result.push_str(&format!("{}", c));
-                result.push_str("%}");
-            }
-            None => {}
-        }
+    let mut output = ZshOutput::new(&format_duration(duration));
+    output.set_color(&dir.config.command_duration_color);
+    Segment::new(vec![output])
+}
 
-        result.push_str(&self.text);
+fn keymap_segment(dir: &DirectoryContext) -> Option<Segment> {
+    let config = dir.config;
+    let mut output = match dir.keymap? {
+        Keymap::Vicmd => ZshOutput::new(&config.vicmd_symbol),
+        Keymap::Viins => ZshOutput::new(&config.viins_symbol),
+    };
+    output.set_color(match dir.keymap? {
+        Keymap::Vicmd => &config.vicmd_color,
+        Keymap::Viins => &config.viins_color,
+    });
+    Segment::new(vec![output])
+}
 
-        match self.color {
-            Some(ref _c) => result.push_str("%f"),
-            None => {}
-        }
+// Who and where we are only matter when it's not the usual: over SSH, or as root.
+fn user_host_segment(config: &Config, environment: &Environment) -> Option<Segment> {
+    let is_root = session::is_root(environment);
+    if !is_root && !session::is_ssh(environment) {
+        return None;
+    }
 
-        if self.is_bold {
-            result.push_str("%b");
-        }
+    let text = match (
+        session::username(environment),
+        session::short_hostname(environment),
+    ) {
+        (Some(username), Some(hostname)) => format!("{}@{}", username, hostname),
+        (username, hostname) => username.or(hostname)?,
+    };
 
-        result
+    let mut output = ZshOutput::new(&text);
+    if is_root {
+        output.set_color(&config.root_user_host_color);
+        output.make_bold();
+    } else {
+        output.set_color(&config.user_host_color);
     }
+    Segment::new(vec![output])
+}
+
+// The character at the very end, which turns into a red `#` with root privileges so they're hard
+// to forget about.
+fn prompt_char_segment(config: &Config, environment: &Environment) -> Option<Segment> {
+    let mut output = if session::is_root(environment) {
+        let mut output = ZshOutput::new(&config.root_prompt_char_symbol);
+        output.set_color(&config.root_prompt_char_color);
+        output
+    } else {
+        let mut output = ZshOutput::new(&config.prompt_char_symbol);
+        output.set_color(&config.prompt_char_color);
+        output
+    };
+    output.make_bold();
+    Segment::new(vec![output])
 }
 
-struct DirectoryContext {
-    path: PathBuf,
-    repository: Option<Repository>,
+fn clock_segment(config: &Config) -> Option<Segment> {
+    let time = clock::format_now(&config.clock_format).filter(|time| !time.is_empty())?;
+
+    let mut output = ZshOutput::new(&time);
+    output.set_color(&config.clock_color);
+    Segment::new(vec![output])
 }
 
-impl DirectoryContext {
-    fn current_directory_short_name(&self) -> Option<String> {
-        self.directory_short_name(&self.path)
+// The segments that come from the shell rather than the repository.
+fn shell_segment(dir: &DirectoryContext, kind: SegmentKind) -> Option<Segment> {
+    match kind {
+        SegmentKind::ExitStatus => exit_status_segment(dir),
+        SegmentKind::CommandDuration => command_duration_segment(dir),
+        SegmentKind::Keymap => keymap_segment(dir),
+        SegmentKind::UserHost => user_host_segment(dir.config, dir.environment),
+        SegmentKind::PromptChar => prompt_char_segment(dir.config, dir.environment),
+        SegmentKind::Clock => clock_segment(dir.config),
+        SegmentKind::Path => path_segment(dir),
+        _ => None,
     }
+}
+
+fn git_summary(dir: &DirectoryContext, renderer: Renderer) -> String {
+    let config = dir.config;
 
-    fn directory_short_name(&self, path: &PathBuf) -> Option<String> {
-        if path.is_dir() {
-            path.file_name()
-                .map(|name_os_str| name_os_str.to_str().map(|name| name.to_string()))
-                .unwrap_or(None)
-        } else {
-            None
+    match dir.repository {
+        Some(repository) => {
+            let segments = summary::summarize(repository, config, dir.environment, &dir.path);
+            segment::join(&segments, &config.separator, renderer)
         }
+        None => not_repo_segment(config)
+            .map(|segment| segment.output(renderer))
+            .unwrap_or_default(),
     }
+}
 
-    fn format_subdirectory_path(
-        &self,
-        repository_path: Option<&std::path::Path>,
-        current_working_directory: &PathBuf,
-    ) -> Option<String> {
-        match repository_path {
-            Some(repository_path) => {
-                let repository_path_buf = repository_path.to_path_buf();
-
-                match self.directory_short_name(&repository_path_buf) {
-                    Some(short_name) => {
-                        let mut result = String::new();
-                        result.push_str(&short_name);
-                        result.push_str("/");
-
-                        let diff = current_working_directory.strip_prefix(repository_path_buf);
-
-                        match diff {
-                            Ok(diff_path) => match diff_path.to_str() {
-                                Some(diff_path_str) => result.push_str(diff_path_str),
-                                None => {}
-                            },
-                            Err(_) => {}
-                        }
-
-                        Some(result)
-                    }
-                    None => None,
-                }
-            }
-            None => None,
-        }
+fn style_segment(config: &Config, kind: SegmentKind, mut segment: Segment) -> Segment {
+    if let Some(style) = config.styles.get(&kind) {
+        segment.apply_style(style);
     }
 
-    fn path_summary(&self) -> Option<String> {
-        match self.repository {
-            Some(ref repository) => {
-                let repository_workdir = repository.workdir();
-                if self.paths_match(repository_workdir, &self.path) {
-                    self.current_directory_short_name()
-                } else {
-                    self.format_subdirectory_path(repository_workdir, &self.path)
-                }
-            }
-            None => self.current_directory_short_name(),
-        }
+    if let Some(background) = config.backgrounds.get(&kind) {
+        segment.set_background(background);
     }
 
-    fn paths_match(
-        &self,
-        repository_path: Option<&std::path::Path>,
-        current_working_directory: &PathBuf,
-    ) -> bool {
-        match repository_path {
-            Some(repository_path) => repository_path == current_working_directory,
-            None => false,
+    segment
+}
+
+// The given segments in order, with the path and exit status filled in here since only we know
+// them. Outside of a repository (or in a bare one) there's just the path and, if asked for, a
+// label saying so.
+fn prompt_segments(
+    dir: &DirectoryContext,
+    kinds: &[SegmentKind],
+    with_label: bool,
+) -> Vec<Segment> {
+    let config = dir.config;
+    let mut git_segments = match dir.repository {
+        Some(repository) => {
+            summary::summarize_segments(repository, config, dir.environment, &dir.path, kinds)
         }
+        None => Vec::new(),
+    };
+
+    let segments: Vec<(SegmentKind, Segment)> = kinds
+        .iter()
+        .enumerate()
+        .filter_map(|(index, kind)| {
+            let segment = if kind.is_from_shell() {
+                shell_segment(dir, *kind)
+            } else {
+                git_segments.get_mut(index).and_then(Option::take)
+            }?;
+
+            Some((*kind, style_segment(config, *kind, segment)))
+        })
+        .collect();
+
+    let label = match dir.repository {
+        _ if !with_label => None,
+        Some(repository) if repository.is_bare() => summary::bare_segment(config),
+        Some(_) => None,
+        None => not_repo_segment(config),
+    };
+
+    // The prompt character stays at the end, even after the label.
+    let position = segments
+        .iter()
+        .position(|(kind, _)| *kind == SegmentKind::PromptChar)
+        .unwrap_or(segments.len());
+    let mut segments: Vec<Segment> = segments.into_iter().map(|(_, segment)| segment).collect();
+    if let Some(label) = label {
+        segments.insert(position, label);
     }
+
+    segments
 }
 
-impl std::fmt::Display for DirectoryContext {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
-        match self.path_summary() {
-            Some(name) => write!(f, "{}", name),
-            None => Ok(()),
+fn render_template(dir: &DirectoryContext, template: &Template, renderer: Renderer) -> String {
+    let config = dir.config;
+    let kinds: Vec<SegmentKind> = template
+        .placeholders()
+        .into_iter()
+        .filter_map(SegmentKind::from_name)
+        .collect();
+    let segments = match dir.repository {
+        Some(repository) => {
+            summary::summarize_segments(repository, config, dir.environment, &dir.path, &kinds)
         }
-    }
+        None => Vec::new(),
+    };
+
+    template.render(
+        |name| match name {
+            "git" => Some(git_summary(dir, renderer)),
+            _ => {
+                let kind = SegmentKind::from_name(name)?;
+                if kind.is_from_shell() {
+                    return shell_segment(dir, kind).map(|segment| segment.output(renderer));
+                }
+
+                let index = kinds.iter().position(|other| *other == kind)?;
+                segments
+                    .get(index)?
+                    .as_ref()
+                    .map(|segment| segment.output(renderer))
+            }
+        },
+        renderer,
+    )
 }
 
-impl std::fmt::Display for ZshOutput {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
-        write!(f, "{}", self.output())
+fn join_segments(dir: &DirectoryContext, segments: &[Segment], renderer: Renderer) -> String {
+    if dir.config.powerline {
+        segment::join_powerline(segments, &dir.config.powerline_symbol, renderer)
+    } else {
+        segment::join(segments, &dir.config.separator, renderer)
     }
 }
 
-fn any_files_changed(repository: &Repository) -> bool {
-    repository
-        .diff_index_to_workdir(None, None)
-        .map_or(false, |diff| diff.deltas().next().is_some())
-}
-
-fn any_untracked_files(repository: &Repository) -> bool {
-    repository.statuses(None).map_or(false, |statuses| {
-        statuses.iter().any(|entry| entry.status().is_wt_new())
-    })
-}
-
-fn summarize(repository: &Repository) -> ZshOutput {
-    match repository.state() {
-        RepositoryState::Clean => match repository.head() {
-            Ok(head_reference) => {
-                let branch_name = if head_reference.is_branch() {
-                    head_reference
-                        .shorthand()
-                        .unwrap_or_else(|| "(unknown branch)")
-                        .to_string()
-                } else {
-                    format!("{}", head_reference.target().unwrap())
-                };
-
-                if any_files_changed(repository) || any_untracked_files(repository) {
-                    let text = format!("{}*", &branch_name);
-                    let mut output = ZshOutput::new(&text);
-                    output.set_color("red");
-                    output
-                } else {
-                    let mut output = ZshOutput::new(&branch_name);
-                    output.set_color("blue");
-                    output
-                }
-            }
-            Err(_) => {
-                let mut output = ZshOutput::new("(no commits yet)");
-                output.set_color("yellow");
-                output
+// The left side is the path unless the config says otherwise, and the right side is everything
+// else, along with the label outside of a repository.
+fn side_segments(dir: &DirectoryContext, side: Side, renderer: Renderer) -> String {
+    let config = dir.config;
+    let (kinds, with_label) = match side {
+        Side::Left => match config.left_segments {
+            Some(ref kinds) => (kinds.clone(), false),
+            None => (vec![SegmentKind::Path], false),
+        },
+        _ => match config.right_segments {
+            Some(ref kinds) => (kinds.clone(), true),
+            None => {
+                let mut kinds = config.segments.clone();
+                kinds.retain(|kind| *kind != SegmentKind::Path);
+                (kinds, true)
             }
         },
-        RepositoryState::Merge => {
-            let mut output = ZshOutput::new("(merging)");
-            output.set_color("magenta");
-            output
+    };
+
+    join_segments(dir, &prompt_segments(dir, &kinds, with_label), renderer)
+}
+
+// Escapes that talk to the terminal rather than draw anything, printed ahead of the left prompt.
+// They'd be garbage in a tmux status line or starship's format.
+fn terminal_escapes(dir: &DirectoryContext, renderer: Renderer) -> String {
+    let shell = match renderer.target {
+        Target::Shell(shell) => shell,
+        Target::Tmux | Target::Starship => return String::new(),
+    };
+
+    let mut escapes = String::new();
+    if dir.config.report_directory {
+        if let Some(url) = session::file_url(&dir.path, dir.environment) {
+            // The percent-encoding means something to zsh too.
+            let url = escape(&url, renderer.target);
+            escapes.push_str(&zero_width(&format!("\x1b]7;{}\x07", url), shell));
         }
-        RepositoryState::Revert => {
-            let mut output = ZshOutput::new("(reverting)");
-            output.set_color("magenta");
-            output
+    }
+    if let Some(title) = title(dir, renderer) {
+        escapes.push_str(&zero_width(&format!("\x1b]0;{}\x07", title), shell));
+    }
+    escapes
+}
+
+// Plain text, since styling has no meaning in a title. Outside a repository the template would
+// mostly come out empty, so it's just the path there.
+fn title(dir: &DirectoryContext, renderer: Renderer) -> Option<String> {
+    let format = dir.config.title.as_ref()?;
+    let renderer = Renderer {
+        styled: false,
+        ..renderer
+    };
+
+    if dir.repository.is_none() {
+        return Some(escape(&dir.to_string(), renderer.target));
+    }
+
+    let template = Template::parse(format)
+        .map_err(|error| eprintln!("my-fancy-zsh-git-prompt: title: {}", error))
+        .ok()?;
+    Some(render_template(dir, &template, renderer))
+}
+
+fn details(dir: DirectoryContext, renderer: Renderer, side: Option<Side>) -> String {
+    let template = dir.config.format.as_ref().and_then(|format| {
+        Template::parse(format)
+            .map_err(|error| eprintln!("my-fancy-zsh-git-prompt: format: {}", error))
+            .ok()
+    });
+
+    let escapes = terminal_escapes(&dir, renderer);
+
+    match (side, template) {
+        (Some(Side::Left), _) => {
+            format!("{}{} ", escapes, side_segments(&dir, Side::Left, renderer))
         }
-        RepositoryState::RevertSequence => {
-            let mut output = ZshOutput::new("(reverting)");
-            output.set_color("magenta");
-            output
+        (Some(Side::Right), _) => side_segments(&dir, Side::Right, renderer),
+        (Some(Side::Both), _) => format!(
+            "{}{} \0{}",
+            escapes,
+            side_segments(&dir, Side::Left, renderer),
+            side_segments(&dir, Side::Right, renderer)
+        ),
+        (None, Some(template)) => {
+            format!("{}{}", escapes, render_template(&dir, &template, renderer))
         }
-        RepositoryState::CherryPick => {
-            let mut output = ZshOutput::new("(cherry-picking)");
-            output.set_color("magenta");
-            output
+        (None, None) => {
+            let segments = prompt_segments(&dir, &dir.config.segments, true);
+            format!("{}{} ", escapes, join_segments(&dir, &segments, renderer))
         }
-        RepositoryState::CherryPickSequence => {
-            let mut output = ZshOutput::new("(cherry-picking)");
-            output.set_color("magenta");
-            output
+    }
+}
+
+// The facts behind the prompt rather than the prompt itself, so nobody has to parse escapes.
+// Outside of a repository there's only the path.
+fn json(dir: DirectoryContext) -> String {
+    let path = dir.path.to_str().map(str::to_string);
+    let mut fields = vec![("path", path.into())];
+
+    match dir.repository {
+        Some(repository) => {
+            let root = repository.workdir().unwrap_or_else(|| repository.path());
+            let root = root
+                .to_str()
+                .map(|root| root.trim_end_matches('/').to_string());
+            fields.push(("repository_root", root.into()));
+            fields.push(("bare", repository.is_bare().into()));
+            fields.extend(summary::status(
+                repository,
+                dir.config,
+                dir.environment,
+                &dir.path,
+            ));
         }
-        RepositoryState::Bisect => {
-            let mut output = ZshOutput::new("(bisecting)");
-            output.set_color("magenta");
-            output
+        None => fields.push(("repository_root", Json::Null)),
+    }
+
+    Json::Object(fields).to_string()
+}
+
+// Prefer $PWD over current_dir() when they agree, because the shell's idea of the working
+// directory keeps any symlinks the user cd'd through.
+fn logical_current_dir(environment: &Environment) -> Option<PathBuf> {
+    let physical = environment.current_dir()?;
+
+    match environment.var_os("PWD").map(PathBuf::from) {
+        Some(logical)
+            if logical.is_absolute()
+                && logical.canonicalize().ok() == physical.canonicalize().ok() =>
+        {
+            Some(logical)
         }
-        RepositoryState::Rebase => {
-            let mut output = ZshOutput::new("(rebasing)");
-            output.set_color("magenta");
-            output
+        _ => Some(physical.to_path_buf()),
+    }
+}
+
+// Resolves `.` and `..` lexically, the way the shell does for `cd`, rather than following
+// symlinks.
+fn normalize(path: &Path) -> PathBuf {
+    let mut result = PathBuf::new();
+
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                result.pop();
+            }
+            other => result.push(other),
         }
-        RepositoryState::RebaseInteractive => {
-            let mut output = ZshOutput::new("(rebasing)");
-            output.set_color("magenta");
-            output
+    }
+
+    result
+}
+
+fn print_prompt(cli: &Cli, environment: &Environment, side: Option<Side>) {
+    if let Some(dir_path) = prompt_path(cli, environment) {
+        let repository = timings::time("discovery", || {
+            discover_repository(cli, environment, &dir_path)
+        });
+        let prompt = timings::time("total", || {
+            render_prompt(cli, environment, side, dir_path, repository.as_ref())
+        });
+        println!("{}", prompt);
+    }
+}
+
+// The directory the prompt is for: `--path`, relative to the working directory if need be.
+fn prompt_path(cli: &Cli, environment: &Environment) -> Option<PathBuf> {
+    match cli.path {
+        Some(ref path) if path.is_absolute() => Some(path.clone()),
+        Some(ref path) => Some(normalize(&logical_current_dir(environment)?.join(path))),
+        None => logical_current_dir(environment),
+    }
+}
+
+// Something to show straight away while the real prompt is worked out: the segments that come
+// from the shell, and the branch read from the HEAD file without opening the repository.
+// Everything else waits, and so do the repository's own config overrides.
+fn render_quick_prompt(cli: &Cli, environment: &Environment, dir_path: PathBuf) -> String {
+    let config = Config::load(&load_options(cli, environment), None);
+    let ceilings = discovery::ceiling_directories(&config.ceiling_directories, environment);
+    let git_dir = discovery::find_git_dir(&dir_path, &ceilings);
+    let dir = DirectoryContext {
+        path: dir_path,
+        repository: None,
+        config: &config,
+        environment,
+        last_exit_code: cli.last_exit_code,
+        command_duration_ms: cli.cmd_duration_ms,
+        keymap: cli.keymap,
+    };
+    let renderer = Renderer {
+        target: Target::Shell(cli.shell),
+        styled: use_color(cli, environment),
+    };
+
+    let segments: Vec<Segment> = config
+        .segments
+        .iter()
+        .filter_map(|kind| {
+            let segment = match kind {
+                SegmentKind::Branch => {
+                    let git_dir = git_dir.as_ref()?;
+                    let branch =
+                        head_file::branch(git_dir, config.sha_length, &config.detached_symbol)?;
+                    let mut output = ZshOutput::new(&branch);
+                    output.set_color(&config.clean_branch_color);
+                    Segment::new(vec![output])
+                }
+                kind if kind.is_from_shell() => shell_segment(&dir, *kind),
+                _ => None,
+            }?;
+
+            Some(style_segment(&config, *kind, segment))
+        })
+        .collect();
+
+    format!(
+        "{}{} ",
+        terminal_escapes(&dir, renderer),
+        join_segments(&dir, &segments, renderer)
+    )
+}
+
+// Prints the quick prompt, then forks to work out the real one. The child lets go of the shell's
+// command substitution straight away, writes the prompt to `file` and sends the shell SIGUSR1 to
+// come and read it.
+#[cfg(unix)]
+fn print_prompt_async(
+    cli: &Cli,
+    environment: &Environment,
+    side: Option<Side>,
+    file: &Path,
+    pid: i32,
+) {
+    let dir_path = match prompt_path(cli, environment) {
+        Some(dir_path) => dir_path,
+        None => return,
+    };
+
+    use std::io::Write;
+
+    println!(
+        "{}",
+        render_quick_prompt(cli, environment, dir_path.clone())
+    );
+    let _ = std::io::stdout().flush();
+
+    if unsafe { libc::fork() } != 0 {
+        return;
+    }
+
+    unsafe {
+        libc::setsid();
+        let null = libc::open(b"/dev/null\0".as_ptr() as *const libc::c_char, libc::O_RDWR);
+        if null >= 0 {
+            libc::dup2(null, libc::STDOUT_FILENO);
+            libc::dup2(null, libc::STDERR_FILENO);
         }
-        RepositoryState::RebaseMerge => {
-            let mut output = ZshOutput::new("(rebasing)");
-            output.set_color("magenta");
-            output
+    }
+
+    let repository = discover_repository(cli, environment, &dir_path);
+    let prompt = render_prompt(cli, environment, side, dir_path, repository.as_ref());
+
+    if write_prompt_file(file, &prompt).is_ok() {
+        unsafe {
+            libc::kill(pid, libc::SIGUSR1);
         }
-        RepositoryState::ApplyMailbox => {
-            let mut output = ZshOutput::new("(mailbox-applying)");
-            output.set_color("magenta");
-            output
+    }
+}
+
+// Written whole and then renamed into place, so the shell never reads half a prompt. The file is
+// only ever created, never opened through a link, and only the user can read it.
+#[cfg(unix)]
+fn write_prompt_file(file: &Path, prompt: &str) -> std::io::Result<()> {
+    use std::fs::{self, OpenOptions};
+    use std::io::Write;
+    use std::os::unix::fs::OpenOptionsExt;
+
+    let mut temporary = file.as_os_str().to_owned();
+    temporary.push(".tmp");
+    let _ = fs::remove_file(&temporary);
+
+    OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .custom_flags(libc::O_NOFOLLOW)
+        .open(&temporary)?
+        .write_all(prompt.as_bytes())?;
+    fs::rename(&temporary, file)
+}
+
+#[cfg(not(unix))]
+fn print_prompt_async(
+    cli: &Cli,
+    environment: &Environment,
+    side: Option<Side>,
+    _file: &Path,
+    _pid: i32,
+) {
+    print_prompt(cli, environment, side);
+}
+
+// The config without any repository's overrides is enough to know where not to look.
+fn discover_repository(
+    cli: &Cli,
+    environment: &Environment,
+    dir_path: &Path,
+) -> Option<Repository> {
+    let config = Config::load(&load_options(cli, environment), None);
+    let ceilings = discovery::ceiling_directories(&config.ceiling_directories, environment);
+    let git_dir = discovery::find_git_dir(dir_path, &ceilings)?;
+
+    Repository::open(git_dir).ok()
+}
+
+fn load_options<'a>(cli: &'a Cli, environment: &'a Environment) -> LoadOptions<'a> {
+    LoadOptions {
+        environment,
+        path: cli.config.as_deref(),
+        theme: cli.theme.as_deref(),
+        ascii: cli.ascii,
+        timeout_ms: cli.timeout_ms,
+    }
+}
+
+// The repository is needed before the config, since it can carry its own overrides.
+fn render_prompt(
+    cli: &Cli,
+    environment: &Environment,
+    side: Option<Side>,
+    dir_path: PathBuf,
+    repository: Option<&Repository>,
+) -> String {
+    let config = timings::time("config", || {
+        Config::load(&load_options(cli, environment), repository)
+    });
+
+    let dir_context = DirectoryContext {
+        path: dir_path,
+        repository,
+        config: &config,
+        environment,
+        last_exit_code: cli.last_exit_code,
+        command_duration_ms: cli.cmd_duration_ms,
+        keymap: cli.keymap,
+    };
+
+    let target = match cli.format {
+        Format::Prompt => Target::Shell(cli.shell),
+        Format::Tmux => Target::Tmux,
+        Format::Starship => Target::Starship,
+        Format::Json => return json(dir_context),
+    };
+    let renderer = Renderer {
+        target,
+        styled: use_color(cli, environment),
+    };
+
+    details(dir_context, renderer, side)
+}
+
+// Follows the NO_COLOR convention (https://no-color.org): any non-empty value turns styling off.
+fn use_color(cli: &Cli, environment: &Environment) -> bool {
+    let no_color = environment
+        .var_os("NO_COLOR")
+        .is_some_and(|value| !value.is_empty());
+    !cli.no_color && !no_color
+}
+
+const ZSH_INIT: &str = r#"setopt prompt_subst
+zmodload zsh/datetime
+typeset -gi _my_fancy_zsh_git_prompt_fd=0
+
+_my_fancy_zsh_git_prompt_stop() {
+  if (( _my_fancy_zsh_git_prompt_fd )); then
+    zle -F $_my_fancy_zsh_git_prompt_fd 2>/dev/null
+    exec {_my_fancy_zsh_git_prompt_fd}<&-
+    _my_fancy_zsh_git_prompt_fd=0
+  fi
+}
+
+_my_fancy_zsh_git_prompt_ready() {
+  local output
+  IFS= read -r -d '' output <&$1
+  _my_fancy_zsh_git_prompt_stop
+  PROMPT="${output%$'\n'}"
+  zle && zle reset-prompt
+}
+
+_my_fancy_zsh_git_prompt_preexec() {
+  typeset -gF _my_fancy_zsh_git_prompt_start=$EPOCHREALTIME
+}
+
+_my_fancy_zsh_git_prompt_precmd() {
+  local exit_code=$?
+  local -a args=(prompt --path "$PWD" --last-exit-code $exit_code)
+  if (( ${+_my_fancy_zsh_git_prompt_start} )); then
+    local -i duration=$(( (EPOCHREALTIME - _my_fancy_zsh_git_prompt_start) * 1000 ))
+    args+=(--cmd-duration-ms $duration)
+    unset _my_fancy_zsh_git_prompt_start
+  fi
+  typeset -ga _my_fancy_zsh_git_prompt_args=($args)
+  if [[ $(bindkey -lL main) == *viins* ]]; then
+    args+=(--keymap viins)
+  fi
+
+  _my_fancy_zsh_git_prompt_stop
+  if [[ -z $_my_fancy_zsh_git_prompt_started ]]; then
+    typeset -g _my_fancy_zsh_git_prompt_started=1
+    PROMPT="$(my-fancy-zsh-git-prompt $args)"
+    return
+  fi
+  exec {_my_fancy_zsh_git_prompt_fd}< <(my-fancy-zsh-git-prompt $args)
+  zle -F $_my_fancy_zsh_git_prompt_fd _my_fancy_zsh_git_prompt_ready
+}
+
+_my_fancy_zsh_git_prompt_keymap_select() {
+  local keymap=viins
+  if [[ $KEYMAP == vicmd ]]; then
+    keymap=vicmd
+  fi
+  PROMPT="$(my-fancy-zsh-git-prompt $_my_fancy_zsh_git_prompt_args --keymap $keymap)"
+  zle reset-prompt
+}
+
+if [[ $(bindkey -lL main) == *viins* ]]; then
+  zle -N zle-keymap-select _my_fancy_zsh_git_prompt_keymap_select
+fi
+
+autoload -Uz add-zsh-hook
+add-zsh-hook preexec _my_fancy_zsh_git_prompt_preexec
+add-zsh-hook precmd _my_fancy_zsh_git_prompt_precmd
+"#;
+
+// Every prompt gets its own file, so a slow one finishing late can't replace a newer one.
+const ZSH_TWO_PHASE_INIT: &str = r#"setopt prompt_subst
+zmodload zsh/datetime
+typeset -g _my_fancy_zsh_git_prompt_file=@PROMPT_DIR@/prompt.$$
+typeset -gi _my_fancy_zsh_git_prompt_count=0
+
+TRAPUSR1() {
+  local file=$_my_fancy_zsh_git_prompt_file.$_my_fancy_zsh_git_prompt_count
+  [[ -r $file ]] || return
+  PROMPT="$(<$file)"
+  zle && zle reset-prompt
+}
+
+_my_fancy_zsh_git_prompt_preexec() {
+  typeset -gF _my_fancy_zsh_git_prompt_start=$EPOCHREALTIME
+}
+
+_my_fancy_zsh_git_prompt_precmd() {
+  local exit_code=$?
+  local -a args=(prompt --path "$PWD" --last-exit-code $exit_code)
+  if (( ${+_my_fancy_zsh_git_prompt_start} )); then
+    local -i duration=$(( (EPOCHREALTIME - _my_fancy_zsh_git_prompt_start) * 1000 ))
+    args+=(--cmd-duration-ms $duration)
+    unset _my_fancy_zsh_git_prompt_start
+  fi
+  typeset -ga _my_fancy_zsh_git_prompt_args=($args)
+  if [[ $(bindkey -lL main) == *viins* ]]; then
+    args+=(--keymap viins)
+  fi
+
+  rm -f $_my_fancy_zsh_git_prompt_file.*(N)
+  (( _my_fancy_zsh_git_prompt_count++ ))
+  local file=$_my_fancy_zsh_git_prompt_file.$_my_fancy_zsh_git_prompt_count
+  PROMPT="$(my-fancy-zsh-git-prompt $args --async-file $file --async-pid $$)"
+}
+
+_my_fancy_zsh_git_prompt_keymap_select() {
+  local keymap=viins
+  if [[ $KEYMAP == vicmd ]]; then
+    keymap=vicmd
+  fi
+  PROMPT="$(my-fancy-zsh-git-prompt $_my_fancy_zsh_git_prompt_args --keymap $keymap)"
+  zle reset-prompt
+}
+
+_my_fancy_zsh_git_prompt_exit() {
+  rm -f $_my_fancy_zsh_git_prompt_file.*(N)
+}
+
+if [[ $(bindkey -lL main) == *viins* ]]; then
+  zle -N zle-keymap-select _my_fancy_zsh_git_prompt_keymap_select
+fi
+
+autoload -Uz add-zsh-hook
+add-zsh-hook preexec _my_fancy_zsh_git_prompt_preexec
+add-zsh-hook precmd _my_fancy_zsh_git_prompt_precmd
+add-zsh-hook zshexit _my_fancy_zsh_git_prompt_exit
+"#;
+
+const BASH_INIT: &str = r#"shopt -s promptvars
+
+_my_fancy_zsh_git_prompt_preexec() {
+  if [[ -z $_my_fancy_zsh_git_prompt_start ]]; then
+    _my_fancy_zsh_git_prompt_start=${EPOCHREALTIME/[.,]/}
+  fi
+}
+
+_my_fancy_zsh_git_prompt_command() {
+  local exit_code=$?
+  local -a args=(--shell bash prompt --path "$PWD" --last-exit-code $exit_code)
+  if [[ -n $_my_fancy_zsh_git_prompt_start ]]; then
+    local now=${EPOCHREALTIME/[.,]/}
+    args+=(--cmd-duration-ms $(( (now - _my_fancy_zsh_git_prompt_start) / 1000 )))
+  fi
+  PS1="$(my-fancy-zsh-git-prompt "${args[@]}")"
+}
+
+_my_fancy_zsh_git_prompt_done() {
+  _my_fancy_zsh_git_prompt_start=
+}
+
+trap _my_fancy_zsh_git_prompt_preexec DEBUG
+PROMPT_COMMAND="_my_fancy_zsh_git_prompt_command${PROMPT_COMMAND:+;$PROMPT_COMMAND}"
+PROMPT_COMMAND+=";_my_fancy_zsh_git_prompt_done"
+"#;
+
+const FISH_INIT: &str = r#"function fish_prompt
+    set -l exit_code $status
+    my-fancy-zsh-git-prompt --shell fish prompt --path "$PWD" \
+        --last-exit-code $exit_code --cmd-duration-ms $CMD_DURATION | string collect
+end
+"#;
+
+const PWSH_INIT: &str = r#"function prompt {
+    $exitCode = if ($?) { 0 } elseif ($LASTEXITCODE) { $LASTEXITCODE } else { 1 }
+    $last = Get-History -Count 1
+    $duration = if ($last) { [long]($last.EndExecutionTime - $last.StartExecutionTime).TotalMilliseconds } else { 0 }
+    (& my-fancy-zsh-git-prompt --shell pwsh prompt --path $PWD.ProviderPath `
+        --last-exit-code $exitCode --cmd-duration-ms $duration) -join ''
+}
+"#;
+
+fn print_init(shell: Shell, daemon: bool, two_phase: bool) {
+    let script = match shell {
+        Shell::Zsh if two_phase => return print_two_phase_init(),
+        _ if two_phase => {
+            return eprintln!("my-fancy-zsh-git-prompt: --two-phase is only for zsh");
         }
-        RepositoryState::ApplyMailboxOrRebase => {
-            let mut output = ZshOutput::new("(mailbox-applying)");
-            output.set_color("magenta");
-            output
+        // After the first one, the prompt is worked out in the background and swapped in when
+        // it's ready, so a slow repository never holds up the shell; until then the previous
+        // prompt stays. The output escapes anything `prompt_subst` would expand, so turning it on
+        // is safe.
+        Shell::Zsh => ZSH_INIT,
+        // The escapes in the output only work with `promptvars` on, which is bash's default.
+        Shell::Bash => BASH_INIT,
+        // `string collect` drops the trailing newline, which fish would otherwise print.
+        Shell::Fish => FISH_INIT,
+        // The prompt is whatever `prompt` returns, so the output is joined back into one string.
+        Shell::Pwsh => PWSH_INIT,
+        Shell::Ansi => {
+            return eprintln!("my-fancy-zsh-git-prompt: there's no shell to set up for ansi")
         }
+    };
+
+    if !daemon {
+        return print!("{}", script);
     }
-}
 
-fn print_details(dir: DirectoryContext) {
-    match dir.repository {
-        Some(ref repository) => {
-            println!("{} {} ", dir, summarize(repository));
+    // The daemon quits straight away if another one is already running, so every shell can try
+    // starting it.
+    let start = match shell {
+        Shell::Zsh | Shell::Bash => "(my-fancy-zsh-git-prompt daemon >/dev/null 2>&1 &)\n",
+        Shell::Fish => "my-fancy-zsh-git-prompt daemon >/dev/null 2>&1 &\ndisown\n",
+        Shell::Pwsh | Shell::Ansi => {
+            return eprintln!("my-fancy-zsh-git-prompt: there's no daemon setup for pwsh")
         }
+    };
+    print!(
+        "{}{}",
+        start,
+        script.replace("prompt --path", "query --path")
+    );
+}
+
+// The prompt files go in a directory only the user can get into, worked out here rather than by
+// the shell.
+fn print_two_phase_init() {
+    let dir = match runtime_dir::private_dir() {
+        Some(dir) => dir,
         None => {
-            let mut output = ZshOutput::new("(not repo)");
-            output.set_color("blue");
-            output.make_bold();
-            println!("{} {} ", dir, output);
+            return eprintln!("my-fancy-zsh-git-prompt: there's nowhere safe for the prompt files")
         }
+    };
+    let dir = match dir.to_str() {
+        Some(dir) => format!("'{}'", dir.replace('\'', "'\\''")),
+        None => return eprintln!("my-fancy-zsh-git-prompt: {}: not unicode", dir.display()),
+    };
+
+    print!("{}", ZSH_TWO_PHASE_INIT.replace("@PROMPT_DIR@", &dir));
+}
+
+// Works out the prompt over and over from scratch, the way the shell asks for it, and reports the
+// median and the 95th percentile, which says more about a slow repository than the average.
+fn print_bench(cli: &Cli, environment: &Environment, iterations: u32) {
+    let dir_path = match prompt_path(cli, environment) {
+        Some(dir_path) => dir_path,
+        None => return,
+    };
+
+    let mut durations: Vec<Duration> = (0..iterations)
+        .map(|_| {
+            let start = Instant::now();
+            let repository = discover_repository(cli, environment, &dir_path);
+            render_prompt(
+                cli,
+                environment,
+                None,
+                dir_path.clone(),
+                repository.as_ref(),
+            );
+            start.elapsed()
+        })
+        .collect();
+    durations.sort();
+
+    let percentile = |percent: usize| {
+        let duration = durations[(durations.len() - 1) * percent / 100];
+        format!("{:.2}ms", duration.as_secs_f64() * 1000.0)
+    };
+    println!("{} ({} iterations)", dir_path.display(), iterations);
+    println!("p50  {}", percentile(50));
+    println!("p95  {}", percentile(95));
+}
+
+fn print_config_path(cli: &Cli, environment: &Environment) {
+    if let Some(path) = cli
+        .config
+        .clone()
+        .or_else(|| config::config_path(environment))
+    {
+        println!("{}", path.display());
     }
 }
 
 fn main() {
-    let dir = current_dir();
-    if dir.is_err() {
-        return;
-    }
+    let cli = Cli::parse();
+    let environment = Environment::current();
 
-    let dir_path = dir.unwrap();
-    let mut dir_context = DirectoryContext {
-        path: dir_path.clone(),
-        repository: None,
-    };
+    if cli.timings {
+        timings::enable();
+    }
 
-    let repository = match Repository::discover(&dir_path) {
-        Ok(r) => r,
-        Err(_) => {
-            print_details(dir_context);
-            return;
+    match cli.command {
+        None => print_prompt(&cli, &environment, None),
+        Some(Command::Prompt {
+            side,
+            ref async_file,
+            async_pid,
+        }) => match (async_file, async_pid) {
+            (Some(file), Some(pid)) => print_prompt_async(&cli, &environment, side, file, pid),
+            _ => print_prompt(&cli, &environment, side),
+        },
+        Some(Command::Init {
+            init_shell,
+            daemon,
+            two_phase,
+        }) => print_init(init_shell.unwrap_or(cli.shell), daemon, two_phase),
+        Some(Command::Config) => print_config_path(&cli, &environment),
+        Some(Command::Daemon { ref socket }) => daemon::serve(socket.as_deref()),
+        Some(Command::Query { side, ref socket }) => {
+            daemon::query(&cli, &environment, side, socket.as_deref())
         }
-    };
+        Some(Command::Bench { iterations }) => print_bench(&cli, &environment, iterations),
+        Some(Command::Completions { completions_shell }) => {
+            completions::print_completions(completions_shell.unwrap_or(cli.shell))
+        }
+    }
+}
 
-    dir_context.repository = Some(repository);
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_reported_directory_is_escaped_for_the_shell() {
+        let config = Config {
+            report_directory: true,
+            ..Config::default()
+        };
+        let environment = Environment::new(Vec::new(), None);
+        let dir = DirectoryContext {
+            path: PathBuf::from("/tmp/my notes/café"),
+            repository: None,
+            config: &config,
+            environment: &environment,
+            last_exit_code: None,
+            command_duration_ms: None,
+            keymap: None,
+        };
+        let renderer = Renderer {
+            target: Target::Shell(Shell::Zsh),
+            styled: true,
+        };
+        let escapes = terminal_escapes(&dir, renderer);
+
+        assert!(escapes.starts_with("%{\x1b]7;file://"));
+        assert!(escapes.ends_with("/tmp/my%%20notes/caf%%C3%%A9\x07%}"));
+    }
 
-    print_details(dir_context);
+    #[test]
+    fn a_template_links_the_path_like_the_default_layout() {
+        let config = Config {
+            hyperlinks: true,
+            ..Config::default()
+        };
+        let environment = Environment::new(Vec::new(), None);
+        let dir = DirectoryContext {
+            path: PathBuf::from("/tmp"),
+            repository: None,
+            config: &config,
+            environment: &environment,
+            last_exit_code: None,
+            command_duration_ms: None,
+            keymap: None,
+        };
+        let renderer = Renderer {
+            target: Target::Shell(Shell::Zsh),
+            styled: true,
+        };
+        let template = Template::parse("{path}").unwrap();
+
+        assert_eq!(
+            render_template(&dir, &template, renderer),
+            path_segment(&dir).unwrap().output(renderer)
+        );
+        assert!(render_template(&dir, &template, renderer).contains("\x1b]8;;file://"));
+    }
 }
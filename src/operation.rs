@@ -0,0 +1,97 @@
+use std::fs;
+use std::path::Path;
+
+pub struct RebaseProgress {
+    pub branch: Option<String>,
+    pub current: Option<usize>,
+    pub total: Option<usize>,
+}
+
+impl RebaseProgress {
+    pub fn label(&self) -> String {
+        let mut result = String::from("(rebasing");
+
+        if let Some(ref branch) = self.branch {
+            result.push(' ');
+            result.push_str(branch);
+        }
+
+        if let (Some(current), Some(total)) = (self.current, self.total) {
+            result.push_str(&format!(" {}/{}", current, total));
+        }
+
+        result.push(')');
+        result
+    }
+}
+
+fn read_trimmed(path: &Path) -> Option<String> {
+    fs::read_to_string(path)
+        .ok()
+        .map(|contents| contents.trim().to_string())
+}
+
+fn read_number(path: &Path) -> Option<usize> {
+    read_trimmed(path).and_then(|contents| contents.parse().ok())
+}
+
+fn read_head_name(path: &Path) -> Option<String> {
+    read_trimmed(path).map(|head_name| {
+        head_name
+            .strip_prefix("refs/heads/")
+            .unwrap_or(&head_name)
+            .to_string()
+    })
+}
+
+pub fn rebase_progress(git_dir: &Path) -> Option<RebaseProgress> {
+    let merge_dir = git_dir.join("rebase-merge");
+    if merge_dir.is_dir() {
+        return Some(RebaseProgress {
+            branch: read_head_name(&merge_dir.join("head-name")),
+            current: read_number(&merge_dir.join("msgnum")),
+            total: read_number(&merge_dir.join("end")),
+        });
+    }
+
+    let apply_dir = git_dir.join("rebase-apply");
+    if apply_dir.is_dir() {
+        return Some(RebaseProgress {
+            branch: read_head_name(&apply_dir.join("head-name")),
+            current: read_number(&apply_dir.join("next")),
+            total: read_number(&apply_dir.join("last")),
+        });
+    }
+
+    None
+}
+
+fn first_line(path: &Path) -> Option<String> {
+    read_trimmed(path).and_then(|contents| contents.lines().next().map(|line| line.to_string()))
+}
+
+pub fn merge_branch(git_dir: &Path) -> Option<String> {
+    let subject = first_line(&git_dir.join("MERGE_MSG"))?;
+    let mut quoted = subject.splitn(3, '\'');
+    quoted.next()?;
+    let branch = quoted.next()?;
+    quoted.next()?;
+
+    Some(branch.to_string())
+}
+
+pub fn merge_head(git_dir: &Path) -> Option<String> {
+    first_line(&git_dir.join("MERGE_HEAD"))
+}
+
+pub fn cherry_pick_head(git_dir: &Path) -> Option<String> {
+    first_line(&git_dir.join("CHERRY_PICK_HEAD"))
+}
+
+pub fn am_progress(git_dir: &Path) -> Option<(usize, usize)> {
+    let apply_dir = git_dir.join("rebase-apply");
+    let current = read_number(&apply_dir.join("next"))?;
+    let total = read_number(&apply_dir.join("last"))?;
+
+    Some((current, total))
+}
@@ -0,0 +1,270 @@
+use std::ffi::OsStr;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use crate::environment::Environment;
+use crate::git::StatusCounts;
+
+// What `git status` says: the same counts libgit2 gives, and with them the commits ahead of and
+// behind the upstream, which would otherwise take a second lookup.
+pub struct Status {
+    pub counts: StatusCounts,
+    // None without an upstream.
+    pub ahead_behind: Option<(usize, usize)>,
+}
+
+// `--no-optional-locks` keeps git from refreshing the index behind the user's back. git sees the
+// environment the prompt is for, like the `GIT_*` variables, rather than whatever this process
+// has.
+fn git(workdir: &Path, environment: &Environment) -> Command {
+    let mut command = Command::new("git");
+    command
+        .env_clear()
+        .envs(environment.vars_os())
+        .arg("--no-optional-locks")
+        .arg("-C")
+        .arg(workdir)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null());
+    command
+}
+
+// Relative to the top of the work tree, which is where git runs.
+fn pathspec_args(pathspec: Option<&Path>) -> Vec<&OsStr> {
+    match pathspec {
+        Some(pathspec) => vec![OsStr::new("--"), pathspec.as_os_str()],
+        None => Vec::new(),
+    }
+}
+
+// None if git couldn't be run, or failed.
+fn output(command: &mut Command) -> Option<Vec<u8>> {
+    let output = command.output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+
+    Some(output.stdout)
+}
+
+// Asks the `git` command instead of libgit2, for the things only it does, like asking a
+// filesystem monitor what changed rather than looking at every file, or reading a sparse index.
+pub fn status(
+    workdir: &Path,
+    environment: &Environment,
+    include_untracked: bool,
+    find_renames: bool,
+    pathspec: Option<&Path>,
+) -> Option<Status> {
+    let output = output(
+        git(workdir, environment)
+            .args(["status", "--porcelain=v2", "--branch", "-z", "--ignored=no"])
+            .arg(if include_untracked {
+                "--untracked-files=all"
+            } else {
+                "--untracked-files=no"
+            })
+            .arg(if find_renames {
+                "--find-renames"
+            } else {
+                "--no-renames"
+            })
+            .args(pathspec_args(pathspec)),
+    )?;
+
+    Some(parse_status(&output))
+}
+
+// Lines inserted and deleted in the index and in the work tree, added together the way libgit2's
+// two diffs are.
+pub fn line_counts(
+    workdir: &Path,
+    environment: &Environment,
+    pathspec: Option<&Path>,
+) -> Option<(usize, usize)> {
+    let staged = output(
+        git(workdir, environment)
+            .args(["diff", "--cached", "--shortstat"])
+            .args(pathspec_args(pathspec)),
+    )?;
+    let unstaged = output(
+        git(workdir, environment)
+            .args(["diff", "--shortstat"])
+            .args(pathspec_args(pathspec)),
+    )?;
+    let (staged_insertions, staged_deletions) = parse_shortstat(&staged);
+    let (insertions, deletions) = parse_shortstat(&unstaged);
+
+    Some((staged_insertions + insertions, staged_deletions + deletions))
+}
+
+// Like ` 2 files changed, 3 insertions(+), 1 deletion(-)`, leaving out whichever is zero, or
+// nothing at all without changes.
+fn parse_shortstat(output: &[u8]) -> (usize, usize) {
+    let output = String::from_utf8_lossy(output);
+    let mut counts = (0, 0);
+
+    for part in output.trim().split(", ") {
+        let (count, label) = match part.split_once(' ') {
+            Some((count, label)) => (count.parse().unwrap_or(0), label),
+            None => continue,
+        };
+        if label.starts_with("insertion") {
+            counts.0 = count;
+        } else if label.starts_with("deletion") {
+            counts.1 = count;
+        }
+    }
+
+    counts
+}
+
+// Entries are NUL-terminated, and a rename (`2`) is followed by an extra field with the path it
+// came from. `XY` is the index and work tree status, with `.` for unchanged. The branch headers
+// come first, as `# branch.<name> <value>`.
+fn parse_status(output: &[u8]) -> Status {
+    let mut status = Status {
+        counts: StatusCounts::default(),
+        ahead_behind: None,
+    };
+    let counts = &mut status.counts;
+    let mut fields = output.split(|byte| *byte == 0);
+
+    while let Some(field) = fields.next() {
+        let (kind, xy) = match field {
+            [b'#', b' ', header @ ..] => {
+                if let Some(ahead_behind) = header.strip_prefix(b"branch.ab ") {
+                    status.ahead_behind = parse_ahead_behind(ahead_behind);
+                }
+                continue;
+            }
+            // Before the changed entries, which an untracked path like `ab c` would also match.
+            [b'?', b' ', ..] => {
+                counts.untracked += 1;
+                continue;
+            }
+            [b'!', b' ', ..] => continue,
+            [kind, b' ', x, y, b' ', ..] => (*kind, (*x, *y)),
+            _ => continue,
+        };
+
+        match kind {
+            b'1' => {}
+            b'2' => {
+                fields.next();
+            }
+            b'u' => {
+                counts.conflicted += 1;
+                continue;
+            }
+            _ => continue,
+        }
+
+        // Counted once each, as `count_statuses` does.
+        let (index, worktree) = xy;
+        if index == b'D' || worktree == b'D' {
+            counts.deleted += 1;
+        } else if index == b'R' || worktree == b'R' {
+            counts.renamed += 1;
+        } else {
+            counts.staged += (index != b'.') as usize;
+            counts.unstaged += (worktree != b'.') as usize;
+        }
+    }
+
+    status
+}
+
+// Like `+1 -2`.
+fn parse_ahead_behind(value: &[u8]) -> Option<(usize, usize)> {
+    let value = std::str::from_utf8(value).ok()?;
+    let (ahead, behind) = value.split_once(' ')?;
+
+    Some((
+        ahead.strip_prefix('+')?.parse().ok()?,
+        behind.strip_prefix('-')?.parse().ok()?,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_changed_renamed_and_conflicted_entries() {
+        let output = [
+            "1 .M N... 100644 100644 100644 3b18e51 3b18e51 README.md",
+            "1 A. N... 000000 100644 100644 0000000 e69de29 new.rs",
+            "1 D. N... 100644 000000 000000 e69de29 0000000 gone.rs",
+            "2 R. N... 100644 100644 100644 e69de29 e69de29 R100 after.rs",
+            "before.rs",
+            "u UU N... 100644 100644 100644 100644 e69de29 e69de29 e69de29 both.rs",
+            "",
+        ]
+        .join("\0");
+        let counts = parse_status(output.as_bytes()).counts;
+
+        assert_eq!(counts.staged, 1);
+        assert_eq!(counts.unstaged, 1);
+        assert_eq!(counts.deleted, 1);
+        assert_eq!(counts.renamed, 1);
+        assert_eq!(counts.conflicted, 1);
+        assert_eq!(counts.untracked, 0);
+    }
+
+    #[test]
+    fn untracked_paths_that_look_like_changed_entries_are_untracked() {
+        let output = ["? ab c", "? notes.txt", "! ab c", ""].join("\0");
+        let counts = parse_status(output.as_bytes()).counts;
+
+        assert_eq!(counts.untracked, 2);
+        assert_eq!(counts.staged, 0);
+        assert_eq!(counts.unstaged, 0);
+    }
+
+    #[test]
+    fn reads_ahead_and_behind_from_the_branch_headers() {
+        let output = [
+            "# branch.oid 3b18e512dba79e4c8300dd08aeb37f8e728b8dad",
+            "# branch.head main",
+            "# branch.upstream origin/main",
+            "# branch.ab +2 -1",
+            "",
+        ]
+        .join("\0");
+
+        assert_eq!(parse_status(output.as_bytes()).ahead_behind, Some((2, 1)));
+    }
+
+    #[test]
+    fn no_upstream_means_no_ahead_and_behind() {
+        let output = ["# branch.oid (initial)", "# branch.head main", ""].join("\0");
+
+        assert_eq!(parse_status(output.as_bytes()).ahead_behind, None);
+    }
+
+    #[test]
+    fn parses_ahead_and_behind() {
+        assert_eq!(parse_ahead_behind(b"+0 -0"), Some((0, 0)));
+        assert_eq!(parse_ahead_behind(b"+12 -3"), Some((12, 3)));
+        assert_eq!(parse_ahead_behind(b"12 3"), None);
+        assert_eq!(parse_ahead_behind(b"+1"), None);
+    }
+
+    #[test]
+    fn parses_shortstat() {
+        assert_eq!(
+            parse_shortstat(b" 2 files changed, 3 insertions(+), 1 deletion(-)\n"),
+            (3, 1)
+        );
+        assert_eq!(
+            parse_shortstat(b" 1 file changed, 1 insertion(+)\n"),
+            (1, 0)
+        );
+        assert_eq!(
+            parse_shortstat(b" 1 file changed, 4 deletions(-)\n"),
+            (0, 4)
+        );
+        assert_eq!(parse_shortstat(b""), (0, 0));
+    }
+}
@@ -0,0 +1,231 @@
+use std::cmp::Ordering;
+use std::fmt;
+
+use serde::{Deserialize, Deserializer};
+
+use crate::segment::SegmentKind;
+
+// Restyles segments when a condition on the repository holds, for example
+//
+//     [[rules]]
+//     when = "changed_files > 20"
+//     segment = "dirty"
+//     style = "red bold"
+//
+// Without a `segment`, the rule restyles every segment.
+#[derive(Deserialize)]
+pub struct Rule {
+    #[serde(deserialize_with = "deserialize_condition")]
+    pub when: Condition,
+    pub segment: Option<SegmentKind>,
+    pub style: String,
+}
+
+impl Rule {
+    pub fn applies_to(&self, kind: SegmentKind) -> bool {
+        self.segment.is_none_or(|segment| segment == kind)
+    }
+}
+
+// Comparisons joined with `and`, like `branch == main and ahead > 0`.
+pub struct Condition {
+    comparisons: Vec<Comparison>,
+}
+
+struct Comparison {
+    fact: String,
+    operator: Operator,
+    value: Value,
+}
+
+#[derive(Clone, Copy)]
+enum Operator {
+    Equal,
+    NotEqual,
+    Greater,
+    GreaterOrEqual,
+    Less,
+    LessOrEqual,
+}
+
+pub enum Value {
+    Number(i64),
+    Text(String),
+}
+
+impl Value {
+    fn parse(text: &str) -> Self {
+        let unquoted = text.trim_matches(|c| c == '"' || c == '\'');
+
+        match unquoted.parse() {
+            Ok(number) if unquoted == text => Value::Number(number),
+            _ => Value::Text(unquoted.to_string()),
+        }
+    }
+
+    // Numbers compare numerically; anything else compares as text, so `branch == 123` works.
+    fn compare(&self, other: &Value) -> Ordering {
+        match (self, other) {
+            (Value::Number(left), Value::Number(right)) => left.cmp(right),
+            _ => self.to_string().cmp(&other.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Number(number) => write!(f, "{}", number),
+            Value::Text(text) => write!(f, "{}", text),
+        }
+    }
+}
+
+impl Condition {
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let comparisons = source
+            .split(" and ")
+            .map(|comparison| {
+                let words: Vec<&str> = comparison.split_whitespace().collect();
+
+                match words.as_slice() {
+                    [fact, operator, value] => Ok(Comparison {
+                        fact: fact.to_string(),
+                        operator: parse_operator(operator)?,
+                        value: Value::parse(value),
+                    }),
+                    _ => Err(format!(
+                        "expected `fact operator value`, found `{}`",
+                        comparison.trim()
+                    )),
+                }
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Condition { comparisons })
+    }
+
+    // Facts are looked up as needed, so a rule about the branch doesn't cost a status scan.
+    pub fn matches<F>(&self, fact: F) -> bool
+    where
+        F: Fn(&str) -> Option<Value>,
+    {
+        self.comparisons.iter().all(|comparison| {
+            let actual = match fact(&comparison.fact) {
+                Some(actual) => actual,
+                None => return false,
+            };
+
+            let ordering = actual.compare(&comparison.value);
+
+            match comparison.operator {
+                Operator::Equal => ordering == Ordering::Equal,
+                Operator::NotEqual => ordering != Ordering::Equal,
+                Operator::Greater => ordering == Ordering::Greater,
+                Operator::GreaterOrEqual => ordering != Ordering::Less,
+                Operator::Less => ordering == Ordering::Less,
+                Operator::LessOrEqual => ordering != Ordering::Greater,
+            }
+        })
+    }
+}
+
+fn parse_operator(operator: &str) -> Result<Operator, String> {
+    match operator {
+        "==" => Ok(Operator::Equal),
+        "!=" => Ok(Operator::NotEqual),
+        ">" => Ok(Operator::Greater),
+        ">=" => Ok(Operator::GreaterOrEqual),
+        "<" => Ok(Operator::Less),
+        "<=" => Ok(Operator::LessOrEqual),
+        _ => Err(format!("unknown operator `{}`", operator)),
+    }
+}
+
+fn deserialize_condition<'de, D>(deserializer: D) -> Result<Condition, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Condition::parse(&String::deserialize(deserializer)?).map_err(serde::de::Error::custom)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A repository on `main`, two ahead, with 25 changed files.
+    fn fact(name: &str) -> Option<Value> {
+        match name {
+            "branch" => Some(Value::Text("main".to_string())),
+            "ahead" => Some(Value::Number(2)),
+            "changed_files" => Some(Value::Number(25)),
+            _ => None,
+        }
+    }
+
+    fn matches(source: &str) -> bool {
+        Condition::parse(source).unwrap().matches(fact)
+    }
+
+    #[test]
+    fn compares_numbers() {
+        assert!(matches("changed_files > 20"));
+        assert!(!matches("changed_files > 25"));
+        assert!(matches("changed_files >= 25"));
+        assert!(matches("ahead < 10"));
+        assert!(!matches("ahead < 2"));
+        assert!(matches("ahead <= 2"));
+        assert!(matches("ahead == 2"));
+        assert!(matches("ahead != 3"));
+    }
+
+    #[test]
+    fn numbers_compare_numerically_rather_than_as_text() {
+        assert!(matches("changed_files > 3"));
+    }
+
+    #[test]
+    fn compares_text_quoted_or_not() {
+        assert!(matches("branch == main"));
+        assert!(matches("branch == \"main\""));
+        assert!(matches("branch == 'main'"));
+        assert!(matches("branch != develop"));
+        assert!(!matches("branch == 123"));
+    }
+
+    #[test]
+    fn a_quoted_number_compares_as_text() {
+        assert!(matches("ahead == \"2\""));
+        assert!(!matches("changed_files > \"3\""));
+    }
+
+    #[test]
+    fn every_comparison_joined_with_and_has_to_hold() {
+        assert!(matches("branch == main and ahead > 0"));
+        assert!(!matches("branch == main and ahead > 2"));
+    }
+
+    #[test]
+    fn unknown_facts_never_match() {
+        assert!(!matches("stashes == 0"));
+        assert!(!matches("stashes != 0"));
+    }
+
+    #[test]
+    fn malformed_conditions_are_errors() {
+        assert_eq!(
+            Condition::parse("ahead").err().unwrap(),
+            "expected `fact operator value`, found `ahead`"
+        );
+        assert_eq!(
+            Condition::parse("branch == main and").err().unwrap(),
+            "expected `fact operator value`, found `branch == main and`"
+        );
+        assert_eq!(
+            Condition::parse("ahead =~ 2").err().unwrap(),
+            "unknown operator `=~`"
+        );
+        assert!(Condition::parse("ahead > 2 3").is_err());
+        assert!(Condition::parse("").is_err());
+    }
+}
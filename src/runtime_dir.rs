@@ -0,0 +1,34 @@
+use std::path::PathBuf;
+
+#[cfg(unix)]
+use std::env;
+#[cfg(unix)]
+use std::fs;
+#[cfg(unix)]
+use std::os::unix::fs::{DirBuilderExt, MetadataExt};
+
+// Where the daemon's socket and the two-phase prompt files go: a directory only the user can get
+// into, so nobody else can plant a link there for us to write through, or a file for the shell
+// to read. `$XDG_RUNTIME_DIR` is private to the user already. Elsewhere it's made in the temporary
+// directory, and one somebody else made, or anyone else can get into, is refused.
+#[cfg(unix)]
+pub fn private_dir() -> Option<PathBuf> {
+    let uid = unsafe { libc::geteuid() };
+    let dir = match env::var_os("XDG_RUNTIME_DIR").filter(|dir| !dir.is_empty()) {
+        Some(runtime_dir) => PathBuf::from(runtime_dir).join("my-fancy-zsh-git-prompt"),
+        None => env::temp_dir().join(format!("my-fancy-zsh-git-prompt-{}", uid)),
+    };
+
+    let _ = fs::DirBuilder::new().mode(0o700).create(&dir);
+    let metadata = fs::symlink_metadata(&dir).ok()?;
+    if !metadata.is_dir() || metadata.uid() != uid || metadata.mode() & 0o077 != 0 {
+        return None;
+    }
+
+    Some(dir)
+}
+
+#[cfg(not(unix))]
+pub fn private_dir() -> Option<PathBuf> {
+    None
+}
@@ -0,0 +1,146 @@
+use serde::{Deserialize, Serialize};
+
+use crate::zsh_output::{Renderer, ZshOutput};
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SegmentKind {
+    ExitStatus,
+    CommandDuration,
+    Keymap,
+    UserHost,
+    PromptChar,
+    Clock,
+    Path,
+    HostIcon,
+    RepositoryName,
+    Operation,
+    Branch,
+    Upstream,
+    Dirty,
+    Staged,
+    Unstaged,
+    Untracked,
+    Stash,
+    Lfs,
+    LineCounts,
+    Ticket,
+    Tag,
+    AheadBehind,
+    BehindDefaultBranch,
+    CommitAge,
+    CommitSubject,
+    Conflicts,
+    UnpushedTotal,
+    IndexLock,
+    Skips,
+    Signing,
+    Identity,
+    Sparse,
+}
+
+impl SegmentKind {
+    // The name used in the config, like `ahead_behind`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        toml::Value::String(name.to_string()).try_into().ok()
+    }
+
+    pub fn name(self) -> String {
+        match toml::Value::try_from(self) {
+            Ok(toml::Value::String(name)) => name,
+            _ => String::new(),
+        }
+    }
+
+    // Segments about the shell session, rather than the repository.
+    pub fn is_from_shell(self) -> bool {
+        matches!(
+            self,
+            SegmentKind::ExitStatus
+                | SegmentKind::CommandDuration
+                | SegmentKind::Keymap
+                | SegmentKind::UserHost
+                | SegmentKind::PromptChar
+                | SegmentKind::Clock
+                | SegmentKind::Path
+        )
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Segment {
+    outputs: Vec<ZshOutput>,
+    background: Option<String>,
+}
+
+impl Segment {
+    pub fn new(outputs: Vec<ZshOutput>) -> Option<Self> {
+        if outputs.is_empty() {
+            None
+        } else {
+            Some(Segment {
+                outputs,
+                background: None,
+            })
+        }
+    }
+
+    pub fn apply_style(&mut self, style: &str) {
+        for output in &mut self.outputs {
+            output.apply_style(style);
+        }
+    }
+
+    pub fn set_background(&mut self, color: &str) {
+        for output in &mut self.outputs {
+            output.set_background(color);
+        }
+        self.background = Some(color.to_string());
+    }
+
+    pub fn output(&self, renderer: Renderer) -> String {
+        self.outputs
+            .iter()
+            .map(|output| output.render(renderer))
+            .collect()
+    }
+}
+
+pub fn join(segments: &[Segment], separator: &str, renderer: Renderer) -> String {
+    segments
+        .iter()
+        .map(|segment| segment.output(renderer))
+        .collect::<Vec<String>>()
+        .join(separator)
+}
+
+// Each segment sits on its own background, padded with a space on either side, and is followed
+// by an arrow in that background color drawn over the next segment's background.
+pub fn join_powerline(segments: &[Segment], symbol: &str, renderer: Renderer) -> String {
+    let mut result = String::new();
+
+    for (index, segment) in segments.iter().enumerate() {
+        let mut padding = ZshOutput::new(" ");
+        if let Some(ref background) = segment.background {
+            padding.set_background(background);
+        }
+
+        result.push_str(&padding.render(renderer));
+        result.push_str(&segment.output(renderer));
+        result.push_str(&padding.render(renderer));
+
+        let mut arrow = ZshOutput::new(symbol);
+        if let Some(ref background) = segment.background {
+            arrow.set_color(background);
+        }
+        if let Some(background) = segments
+            .get(index + 1)
+            .and_then(|next| next.background.as_ref())
+        {
+            arrow.set_background(background);
+        }
+        result.push_str(&arrow.render(renderer));
+    }
+
+    result
+}
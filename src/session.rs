@@ -0,0 +1,110 @@
+use std::path::Path;
+
+use crate::environment::Environment;
+use crate::text::percent_encode;
+
+pub fn is_ssh(environment: &Environment) -> bool {
+    environment.var("SSH_CONNECTION").is_some() || environment.var("SSH_TTY").is_some()
+}
+
+#[cfg(unix)]
+pub fn is_root(_environment: &Environment) -> bool {
+    unsafe { libc::geteuid() == 0 }
+}
+
+// Only an elevated token can list the system account's profile, which saves asking the Windows
+// security APIs.
+#[cfg(windows)]
+pub fn is_root(environment: &Environment) -> bool {
+    let system_root = environment.var("SystemRoot").unwrap_or(r"C:\Windows");
+    std::fs::read_dir(format!(r"{}\System32\config\systemprofile", system_root)).is_ok()
+}
+
+#[cfg(not(any(unix, windows)))]
+pub fn is_root(_environment: &Environment) -> bool {
+    false
+}
+
+// The effective user, which after `su` or `sudo -s` isn't necessarily who `$USER` says.
+pub fn username(environment: &Environment) -> Option<String> {
+    system_username().or_else(|| {
+        ["USER", "LOGNAME", "USERNAME"]
+            .iter()
+            .find_map(|name| environment.var(name))
+            .map(str::to_string)
+    })
+}
+
+#[cfg(unix)]
+fn system_username() -> Option<String> {
+    unsafe {
+        let passwd = libc::getpwuid(libc::geteuid());
+        if passwd.is_null() || (*passwd).pw_name.is_null() {
+            return None;
+        }
+
+        std::ffi::CStr::from_ptr((*passwd).pw_name)
+            .to_str()
+            .ok()
+            .map(str::to_string)
+    }
+}
+
+#[cfg(not(unix))]
+fn system_username() -> Option<String> {
+    None
+}
+
+pub fn hostname(environment: &Environment) -> Option<String> {
+    system_hostname().or_else(|| {
+        ["HOSTNAME", "COMPUTERNAME"]
+            .iter()
+            .find_map(|name| environment.var(name))
+            .map(str::to_string)
+    })
+}
+
+// Just the first label, like `devbox` for `devbox.example.com`.
+pub fn short_hostname(environment: &Environment) -> Option<String> {
+    hostname(environment)?.split('.').next().map(str::to_string)
+}
+
+// A `file://` URL naming this machine, so a terminal can tell a local path from a remote one.
+// Windows paths like `C:\src` become `/C:/src`.
+pub fn file_url(path: &Path, environment: &Environment) -> Option<String> {
+    let mut path = path.to_str()?.to_string();
+    if cfg!(windows) {
+        path = path.replace('\\', "/");
+    }
+    let path = if path.starts_with('/') {
+        path
+    } else {
+        format!("/{}", path)
+    };
+
+    Some(format!(
+        "file://{}{}",
+        hostname(environment).unwrap_or_default(),
+        percent_encode(&path)
+    ))
+}
+
+#[cfg(unix)]
+fn system_hostname() -> Option<String> {
+    let mut buffer = [0u8; 256];
+    let result =
+        unsafe { libc::gethostname(buffer.as_mut_ptr() as *mut libc::c_char, buffer.len()) };
+    if result != 0 {
+        return None;
+    }
+
+    let length = buffer.iter().position(|byte| *byte == 0)?;
+    String::from_utf8(buffer[..length].to_vec())
+        .ok()
+        .filter(|hostname| !hostname.is_empty())
+}
+
+#[cfg(not(unix))]
+fn system_hostname() -> Option<String> {
+    None
+}
@@ -0,0 +1,1548 @@
+use std::cell::{Cell, OnceCell};
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Mutex, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use git2::{Oid, Repository, RepositoryState};
+
+use crate::cache;
+use crate::config::{Backend, Config, Truncation};
+use crate::environment::Environment;
+use crate::git;
+use crate::json::Json;
+use crate::lfs;
+use crate::operation;
+use crate::porcelain;
+use crate::remote;
+use crate::rules::Value;
+use crate::segment::{Segment, SegmentKind};
+use crate::text::{abbreviate_sha, format_age, truncate, truncate_middle};
+use crate::timings;
+use crate::zsh_output::ZshOutput;
+
+struct Head {
+    // The branch shorthand, or a description of the commit when detached.
+    name: String,
+    is_branch: bool,
+    oid: Option<Oid>,
+}
+
+// What the work tree scan has to look for, worked out from the segments being shown and the
+// config, so that it can be carried to another thread.
+#[derive(Clone)]
+struct Scan {
+    // Walking the work tree for untracked files is the slowest part of the status, so it's
+    // skipped unless a segment shows them, and whether the repository allows it is only checked
+    // once the scan is underway.
+    untracked: bool,
+    untracked_max_index_entries: Option<usize>,
+    renames: bool,
+    // Counting lines means diffing the contents.
+    lines: bool,
+    // Ask the `git` command rather than libgit2.
+    git: bool,
+    // Only look beneath this directory, relative to the top of the work tree.
+    pathspec: Option<PathBuf>,
+    // What `git` runs with.
+    environment: Environment,
+}
+
+impl Scan {
+    fn new(
+        config: &Config,
+        environment: &Environment,
+        kinds: &[SegmentKind],
+        scope: Option<&Path>,
+    ) -> Self {
+        Scan {
+            untracked: config.show_untracked
+                && kinds
+                    .iter()
+                    .any(|kind| matches!(kind, SegmentKind::Dirty | SegmentKind::Untracked)),
+            untracked_max_index_entries: config.untracked_max_index_entries,
+            renames: kinds.contains(&SegmentKind::Dirty),
+            lines: kinds.contains(&SegmentKind::LineCounts),
+            git: false,
+            pathspec: scope.map(Path::to_path_buf),
+            environment: environment.clone(),
+        }
+    }
+
+    // Like `GIT_PS1_SHOWUNTRACKEDFILES`, there's a way out of the walk: turning it off in the
+    // config (or for one repository with `git config prompt.show-untracked false`), git's own
+    // `status.showUntrackedFiles = no`, or an index big enough that the walk would be slow.
+    fn wants_untracked(&self, repository: &Repository) -> bool {
+        self.untracked
+            && git::shows_untracked_files(repository)
+            && self
+                .untracked_max_index_entries
+                .is_none_or(|limit| git::count_index_entries(repository) <= limit)
+    }
+}
+
+// What the work tree scan found, as counts rather than the diffs themselves so that the scan can
+// run on another thread.
+struct Changes {
+    staged_files: usize,
+    unstaged_files: usize,
+    // None when untracked files weren't looked for.
+    untracked_count: Option<usize>,
+    conflicted_files: usize,
+    deleted_files: usize,
+    renamed_files: usize,
+    insertions: usize,
+    deletions: usize,
+    // When the `git` command gave the status, what it said about the upstream too: the commits
+    // ahead and behind, or None without one.
+    ahead_behind: Option<Option<(usize, usize)>>,
+}
+
+impl Changes {
+    // If `git` can't be run, libgit2 does the job after all.
+    fn scan(repository: &Repository, scan: Scan) -> Self {
+        let untracked = scan.wants_untracked(repository);
+        let pathspec = scan.pathspec.as_deref();
+        let status = match repository.workdir() {
+            Some(workdir) if scan.git => porcelain::status(
+                workdir,
+                &scan.environment,
+                untracked,
+                scan.renames,
+                pathspec,
+            ),
+            _ => None,
+        };
+        let (counts, ahead_behind) = match status {
+            Some(status) => (status.counts, Some(status.ahead_behind)),
+            None => (
+                git::count_statuses(repository, untracked, scan.renames, pathspec),
+                None,
+            ),
+        };
+        let git_line_counts = match repository.workdir() {
+            Some(workdir) if scan.git && scan.lines => {
+                porcelain::line_counts(workdir, &scan.environment, pathspec)
+            }
+            _ => None,
+        };
+        let (insertions, deletions) = match git_line_counts {
+            Some(line_counts) => line_counts,
+            None if scan.lines => {
+                let staged = git::staged_diff(repository, pathspec);
+                let unstaged = git::unstaged_diff(repository, pathspec);
+                git::line_counts(&[&staged, &unstaged])
+            }
+            None => (0, 0),
+        };
+
+        Changes {
+            staged_files: counts.staged,
+            unstaged_files: counts.unstaged,
+            untracked_count: Some(counts.untracked).filter(|_| untracked),
+            conflicted_files: counts.conflicted,
+            deleted_files: counts.deleted,
+            renamed_files: counts.renamed,
+            insertions,
+            deletions,
+            ahead_behind,
+        }
+    }
+
+    fn is_staged(&self) -> bool {
+        self.staged_files > 0
+    }
+
+    fn is_changed(&self) -> bool {
+        self.unstaged_files > 0
+    }
+
+    fn is_dirty(&self) -> bool {
+        self.is_staged()
+            || self.is_changed()
+            || self.untracked_count.unwrap_or(0) > 0
+            || self.deleted_files > 0
+            || self.renamed_files > 0
+    }
+}
+
+// A lookup running on a thread of its own, with its own handle on the repository since one can't
+// be shared between threads. If it runs past the deadline it's left to finish on its own, and
+// the answer is thrown away.
+struct Job<T>(mpsc::Receiver<T>);
+
+// The lookups still running, by git directory and name. The daemon lives long enough to ask a
+// repository that's slow for good over and over, and each prompt would otherwise start another
+// lookup alongside the ones that haven't finished yet. Until the first is done, the others miss
+// their deadline straight away.
+static RUNNING: Mutex<Vec<(PathBuf, &'static str)>> = Mutex::new(Vec::new());
+
+// Takes a lookup off `RUNNING` however its thread ends.
+struct Running((PathBuf, &'static str));
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        RUNNING
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .retain(|running| *running != self.0);
+    }
+}
+
+impl<T: Send + 'static> Job<T> {
+    // Opened from the git directory, with the same work tree, since opening the work tree could
+    // find another repository when `GIT_DIR` or `core.worktree` put them apart.
+    fn spawn(
+        repository: &Repository,
+        name: &'static str,
+        work: impl FnOnce(&Repository) -> T + Send + 'static,
+    ) -> Self {
+        let git_dir = repository.path().to_path_buf();
+        let workdir = repository.workdir().map(Path::to_path_buf);
+        let (sender, receiver) = mpsc::channel();
+
+        let key = (git_dir.clone(), name);
+        {
+            let mut running = RUNNING.lock().unwrap_or_else(PoisonError::into_inner);
+            if running.contains(&key) {
+                return Job(receiver);
+            }
+            running.push(key.clone());
+        }
+
+        thread::spawn(move || {
+            let _running = Running(key);
+            let repository = match Repository::open(git_dir) {
+                Ok(repository) => repository,
+                Err(_) => return,
+            };
+            if let Some(workdir) = workdir.filter(|workdir| repository.workdir() != Some(workdir)) {
+                if repository.set_workdir(&workdir, false).is_err() {
+                    return;
+                }
+            }
+            let _ = sender.send(work(&repository));
+        });
+
+        Job(receiver)
+    }
+
+    fn join(&self, deadline: Option<Instant>) -> Option<T> {
+        match deadline {
+            Some(deadline) => self
+                .0
+                .recv_timeout(deadline.saturating_duration_since(Instant::now()))
+                .ok(),
+            None => self.0.recv().ok(),
+        }
+    }
+}
+
+// One of the slow lookups: maybe started ahead of time, and answered at most once. The answer is
+// None when it missed the deadline.
+struct Lookup<T> {
+    // For `--timings`.
+    name: &'static str,
+    job: OnceCell<Job<T>>,
+    answer: OnceCell<Option<T>>,
+}
+
+impl<T> Lookup<T> {
+    fn new(name: &'static str) -> Self {
+        Lookup {
+            name,
+            job: OnceCell::new(),
+            answer: OnceCell::new(),
+        }
+    }
+}
+
+// Everything beyond the repository and config is worked out the first time a segment asks for
+// it, so segments that aren't shown cost nothing.
+struct Context<'repo> {
+    repository: &'repo Repository,
+    config: &'repo Config,
+    head: OnceCell<Option<Head>>,
+    scan: Scan,
+    uses_git: OnceCell<bool>,
+    // Whether `git status` is already being asked, and can say how far ahead and behind the
+    // branch is too.
+    status_has_ahead_behind: bool,
+    // Whether the branch is coloured by whether the work tree is dirty, which is only worth it
+    // when a segment scans the work tree anyway. A branch on its own shouldn't cost a scan.
+    branch_shows_dirty: bool,
+    // From `timeout_ms`, shared by all the lookups.
+    deadline: Option<Instant>,
+    timed_out: Cell<bool>,
+    changes: Lookup<Changes>,
+    stashes: Lookup<usize>,
+    ahead_behind: Lookup<Option<(usize, usize)>>,
+    submodules_dirty: Lookup<bool>,
+}
+
+impl<'repo> Context<'repo> {
+    // When more than one of the slow lookups will be needed, they all start straight away on
+    // threads of their own, so the prompt waits for the slowest of them rather than all of them
+    // added up. One on its own isn't worth the extra handle on the repository. Whether the
+    // submodules are needed is asked separately, since not everything that scans like the dirty
+    // segment shows them.
+    fn new(
+        repository: &'repo Repository,
+        config: &'repo Config,
+        environment: &Environment,
+        kinds: &[SegmentKind],
+        scope: Option<&Path>,
+        wants_submodules: bool,
+    ) -> Self {
+        let mut context = Context {
+            repository,
+            config,
+            head: OnceCell::new(),
+            scan: Scan::new(config, environment, kinds, scope),
+            uses_git: OnceCell::new(),
+            status_has_ahead_behind: false,
+            branch_shows_dirty: false,
+            deadline: config
+                .timeout_ms
+                .map(|timeout_ms| Instant::now() + Duration::from_millis(timeout_ms)),
+            timed_out: Cell::new(false),
+            changes: Lookup::new("status scan"),
+            stashes: Lookup::new("stash count"),
+            ahead_behind: Lookup::new("upstream lookup"),
+            submodules_dirty: Lookup::new("submodule check"),
+        };
+
+        let wants = |wanted: &[SegmentKind]| kinds.iter().any(|kind| wanted.contains(kind));
+        let wants_changes = wants(&[
+            SegmentKind::Dirty,
+            SegmentKind::Staged,
+            SegmentKind::Unstaged,
+            SegmentKind::Untracked,
+            SegmentKind::LineCounts,
+            SegmentKind::Conflicts,
+        ]);
+        let wants_stashes = wants(&[SegmentKind::Stash]);
+        context.branch_shows_dirty = wants_changes;
+        context.status_has_ahead_behind =
+            wants(&[SegmentKind::AheadBehind]) && wants_changes && context.uses_git();
+        let wants_ahead_behind =
+            wants(&[SegmentKind::AheadBehind]) && !context.status_has_ahead_behind;
+
+        let wanted = [
+            wants_changes,
+            wants_stashes,
+            wants_ahead_behind,
+            wants_submodules,
+        ];
+        if wanted.iter().filter(|wanted| **wanted).count() > 1 {
+            if wants_changes {
+                context.start(&context.changes, context.scan_changes());
+            }
+            if wants_stashes {
+                context.start(&context.stashes, git::count_stashes);
+            }
+            if wants_ahead_behind {
+                context.start(&context.ahead_behind, context.count_ahead_behind());
+            }
+            if wants_submodules {
+                context.start(&context.submodules_dirty, git::any_submodules_dirty);
+            }
+        }
+
+        context
+    }
+
+    fn start<T: Send + 'static>(
+        &self,
+        lookup: &Lookup<T>,
+        work: impl FnOnce(&Repository) -> T + Send + 'static,
+    ) {
+        lookup
+            .job
+            .get_or_init(|| Job::spawn(self.repository, lookup.name, work));
+    }
+
+    // Waits for the lookup if it was started ahead of time, or does it now: on a thread of its
+    // own if there's a deadline to keep, and right here otherwise.
+    fn answer<'a, T: Send + 'static>(
+        &self,
+        lookup: &'a Lookup<T>,
+        work: impl FnOnce(&Repository) -> T + Send + 'static,
+    ) -> Option<&'a T> {
+        lookup
+            .answer
+            .get_or_init(|| {
+                let answer = timings::time(lookup.name, || match lookup.job.get() {
+                    Some(job) => job.join(self.deadline),
+                    None if self.deadline.is_some() => {
+                        Job::spawn(self.repository, lookup.name, work).join(self.deadline)
+                    }
+                    None => Some(work(self.repository)),
+                });
+                if answer.is_none() {
+                    self.timed_out.set(true);
+                }
+                answer
+            })
+            .as_ref()
+    }
+
+    fn scan_changes(&self) -> impl FnOnce(&Repository) -> Changes + Send + 'static {
+        let scan = Scan {
+            git: self.uses_git(),
+            ..self.scan.clone()
+        };
+        move |repository| Changes::scan(repository, scan)
+    }
+
+    // libgit2 can't talk to a filesystem monitor, and without one it has to look at every file.
+    // Nor can it read a sparse index, which would leave the status empty.
+    fn uses_git(&self) -> bool {
+        *self.uses_git.get_or_init(|| match self.config.backend {
+            Backend::Auto => {
+                git::uses_fsmonitor(self.repository) || git::uses_sparse_index(self.repository)
+            }
+            Backend::Libgit2 => false,
+            Backend::Git => true,
+        })
+    }
+
+    fn count_ahead_behind(
+        &self,
+    ) -> impl FnOnce(&Repository) -> Option<(usize, usize)> + Send + 'static {
+        let branch_name = self.branch_name().map(str::to_string);
+        move |repository| git::ahead_behind(repository, branch_name.as_deref()?)
+    }
+
+    // Describing a detached HEAD can mean walking history, so it waits until something asks.
+    fn head(&self) -> Option<&Head> {
+        self.head.get_or_init(|| self.read_head()).as_ref()
+    }
+
+    fn read_head(&self) -> Option<Head> {
+        let (repository, config) = (self.repository, self.config);
+
+        repository.head().ok().map(|head_reference| {
+            let name = if head_reference.is_branch() {
+                head_reference
+                    .shorthand()
+                    .unwrap_or("(unknown branch)")
+                    .to_string()
+            } else {
+                let description =
+                    git::describe_head(repository, config.sha_length).unwrap_or_else(|| {
+                        let sha = head_reference.target().unwrap().to_string();
+                        abbreviate_sha(&sha, config.sha_length).to_string()
+                    });
+                format!("{} {}", config.detached_symbol, description)
+            };
+
+            Head {
+                name,
+                is_branch: head_reference.is_branch(),
+                oid: head_reference.target(),
+            }
+        })
+    }
+
+    fn branch_name(&self) -> Option<&str> {
+        match self.head() {
+            Some(head) if head.is_branch => Some(&head.name),
+            _ => None,
+        }
+    }
+
+    // The values that styling rules can test.
+    fn fact(&self, name: &str) -> Option<Value> {
+        let count = |count: usize| Some(Value::Number(count as i64));
+
+        match name {
+            "branch" => self.branch_name().map(|name| Value::Text(name.to_string())),
+            "state" => Some(Value::Text(state_name(self.repository.state()).to_string())),
+            "staged_files" => count(self.changes()?.staged_files),
+            "unstaged_files" => count(self.changes()?.unstaged_files),
+            "untracked_files" => count(self.changes()?.untracked_count?),
+            "changed_files" => {
+                let changes = self.changes()?;
+                let untracked_count = changes.untracked_count.unwrap_or(0);
+                count(
+                    changes.staged_files
+                        + changes.unstaged_files
+                        + changes.deleted_files
+                        + changes.renamed_files
+                        + untracked_count,
+                )
+            }
+            "conflicted_files" => count(self.changes()?.conflicted_files),
+            "deleted_files" => count(self.changes()?.deleted_files),
+            "renamed_files" => count(self.changes()?.renamed_files),
+            "ahead" | "behind" => {
+                let (ahead, behind) = self.ahead_behind()?;
+                count(if name == "ahead" { ahead } else { behind })
+            }
+            _ => None,
+        }
+    }
+
+    // None when the scan didn't finish within `timeout_ms`.
+    fn changes(&self) -> Option<&Changes> {
+        self.answer(&self.changes, self.scan_changes())
+    }
+
+    fn count_stashes(&self) -> Option<usize> {
+        self.answer(&self.stashes, git::count_stashes).copied()
+    }
+
+    fn ahead_behind(&self) -> Option<(usize, usize)> {
+        if self.status_has_ahead_behind {
+            if let Some(ahead_behind) = self.changes().and_then(|changes| changes.ahead_behind) {
+                return ahead_behind;
+            }
+        }
+
+        *self.answer(&self.ahead_behind, self.count_ahead_behind())?
+    }
+
+    fn any_submodules_dirty(&self) -> bool {
+        self.answer(&self.submodules_dirty, git::any_submodules_dirty)
+            .is_some_and(|dirty| *dirty)
+    }
+}
+
+fn state_name(state: RepositoryState) -> &'static str {
+    match state {
+        RepositoryState::Clean => "clean",
+        RepositoryState::Merge => "merge",
+        RepositoryState::Revert | RepositoryState::RevertSequence => "revert",
+        RepositoryState::CherryPick | RepositoryState::CherryPickSequence => "cherry_pick",
+        RepositoryState::Bisect => "bisect",
+        RepositoryState::Rebase
+        | RepositoryState::RebaseInteractive
+        | RepositoryState::RebaseMerge => "rebase",
+        RepositoryState::ApplyMailbox | RepositoryState::ApplyMailboxOrRebase => "am",
+    }
+}
+
+fn format_file_count(symbol: &str, count: usize, config: &Config) -> String {
+    if config.show_file_counts {
+        format!("{}{}", symbol, count)
+    } else {
+        symbol.to_string()
+    }
+}
+
+fn format_ahead_behind(ahead: usize, behind: usize, config: &Config) -> String {
+    let mut result = String::new();
+
+    if ahead > 0 {
+        result.push_str(&format!("{}{}", config.ahead_symbol, ahead));
+    }
+
+    if behind > 0 {
+        result.push_str(&format!("{}{}", config.behind_symbol, behind));
+    }
+
+    result
+}
+
+fn strip_branch_prefix<'a>(branch_name: &'a str, config: &Config) -> (&'a str, &'a str) {
+    for pattern in &config.branch_strip_patterns {
+        if let Some(prefix) = pattern.find(branch_name) {
+            if prefix.start() == 0 && prefix.end() < branch_name.len() {
+                return branch_name.split_at(prefix.end());
+            }
+        }
+    }
+
+    ("", branch_name)
+}
+
+fn extract_ticket(branch_name: &str, config: &Config) -> Option<String> {
+    let captures = config.ticket_pattern.as_ref()?.captures(branch_name)?;
+    let ticket = captures.get(1).or_else(|| captures.get(0))?;
+
+    Some(ticket.as_str().to_string())
+}
+
+pub fn summarize(
+    repository: &Repository,
+    config: &Config,
+    environment: &Environment,
+    directory: &Path,
+) -> Vec<Segment> {
+    if repository.is_bare() {
+        return bare_segment(config).into_iter().collect();
+    }
+
+    summarize_segments(repository, config, environment, directory, &config.segments)
+        .into_iter()
+        .flatten()
+        .collect()
+}
+
+pub fn bare_segment(config: &Config) -> Option<Segment> {
+    let mut output = ZshOutput::new("(bare)");
+    output.set_color(&config.bare_color);
+    Segment::new(vec![output])
+}
+
+// What `--format json` reports about the repository. The counts and names match the facts that
+// rules can test.
+pub fn status(
+    repository: &Repository,
+    config: &Config,
+    environment: &Environment,
+    directory: &Path,
+) -> Vec<(&'static str, Json)> {
+    let scope = status_scope(repository, config, directory);
+    let context = Context::new(
+        repository,
+        config,
+        environment,
+        &[SegmentKind::Dirty, SegmentKind::AheadBehind],
+        scope.as_deref(),
+        false,
+    );
+    let head = context.head();
+    let fact = |name| match context.fact(name) {
+        Some(Value::Number(number)) => Json::Number(number),
+        Some(Value::Text(text)) => Json::String(text),
+        None => Json::Null,
+    };
+    // There's no work tree to compare against in a bare repository.
+    let count = |name| {
+        if repository.is_bare() {
+            Json::Null
+        } else {
+            fact(name)
+        }
+    };
+
+    vec![
+        ("branch", fact("branch")),
+        ("detached", head.is_some_and(|head| !head.is_branch).into()),
+        (
+            "sha",
+            head.and_then(|head| head.oid)
+                .map(|oid| oid.to_string())
+                .into(),
+        ),
+        ("state", fact("state")),
+        (
+            "dirty",
+            match context.changes() {
+                _ if repository.is_bare() => false.into(),
+                changes => changes.map(Changes::is_dirty).into(),
+            },
+        ),
+        ("staged_files", count("staged_files")),
+        ("unstaged_files", count("unstaged_files")),
+        ("untracked_files", count("untracked_files")),
+        ("conflicted_files", count("conflicted_files")),
+        ("deleted_files", count("deleted_files")),
+        ("renamed_files", count("renamed_files")),
+        (
+            "status_scope",
+            scope
+                .as_deref()
+                .and_then(Path::to_str)
+                .map(str::to_string)
+                .into(),
+        ),
+        ("ahead", fact("ahead")),
+        ("behind", fact("behind")),
+    ]
+}
+
+// One entry per kind, in order, with None where a segment has nothing to show.
+pub fn summarize_segments(
+    repository: &Repository,
+    config: &Config,
+    environment: &Environment,
+    directory: &Path,
+    kinds: &[SegmentKind],
+) -> Vec<Option<Segment>> {
+    // The path on its own, say, needs nothing from the repository.
+    if repository.is_bare() || kinds.iter().all(|kind| kind.is_from_shell()) {
+        return kinds.iter().map(|_| None).collect();
+    }
+
+    let scope = status_scope(repository, config, directory);
+    let scope = scope.as_deref();
+
+    if let Some(segments) = cache::recall(repository, config, kinds, scope) {
+        return segments;
+    }
+
+    if config.cache_seconds > 0 {
+        if let Some(segments) = timings::time("cache", || {
+            cache::read(repository, config, kinds, scope, environment)
+        }) {
+            return segments;
+        }
+    }
+
+    // A scan that ran out of time isn't worth keeping.
+    let (segments, complete) = compute_segments(repository, config, environment, kinds, scope);
+    if !complete {
+        return segments;
+    }
+
+    cache::remember(repository, config, kinds, scope, &segments);
+    if config.cache_seconds > 0 {
+        cache::write(repository, config, kinds, scope, environment, segments)
+    } else {
+        segments
+    }
+}
+
+// With `scope_status_to_directory`, where the directory is in the work tree, for the status to
+// count only what's beneath it. None at the top, where that's everything anyway.
+fn status_scope(repository: &Repository, config: &Config, directory: &Path) -> Option<PathBuf> {
+    if !config.scope_status_to_directory {
+        return None;
+    }
+
+    let workdir = repository.workdir()?;
+    let relative = match directory.strip_prefix(workdir) {
+        Ok(relative) => relative.to_path_buf(),
+        // libgit2 resolves symlinks in the work tree's path, and the shell keeps them.
+        Err(_) => directory
+            .canonicalize()
+            .ok()?
+            .strip_prefix(workdir.canonicalize().ok()?)
+            .ok()?
+            .to_path_buf(),
+    };
+
+    Some(relative).filter(|relative| !relative.as_os_str().is_empty())
+}
+
+// Along with whether the slow lookups, if there were any, finished in time.
+fn compute_segments(
+    repository: &Repository,
+    config: &Config,
+    environment: &Environment,
+    kinds: &[SegmentKind],
+    scope: Option<&Path>,
+) -> (Vec<Option<Segment>>, bool) {
+    let wants_submodules = kinds.contains(&SegmentKind::Dirty);
+    let context = Context::new(
+        repository,
+        config,
+        environment,
+        kinds,
+        scope,
+        wants_submodules,
+    );
+
+    let segments = kinds
+        .iter()
+        .map(|kind| {
+            let outputs = timings::time(&kind.name(), || segment_outputs(*kind, &context));
+            let mut segment = Segment::new(outputs)?;
+
+            for rule in &config.rules {
+                if rule.applies_to(*kind) && rule.when.matches(|name| context.fact(name)) {
+                    segment.apply_style(&rule.style);
+                }
+            }
+
+            Some(segment)
+        })
+        .collect();
+
+    (segments, !context.timed_out.get())
+}
+
+fn segment_outputs(kind: SegmentKind, context: &Context) -> Vec<ZshOutput> {
+    match kind {
+        // These are filled in by the caller, which knows the working directory and the shell.
+        SegmentKind::ExitStatus
+        | SegmentKind::CommandDuration
+        | SegmentKind::Keymap
+        | SegmentKind::UserHost
+        | SegmentKind::PromptChar
+        | SegmentKind::Clock
+        | SegmentKind::Path => Vec::new(),
+        SegmentKind::HostIcon => host_icon(context),
+        SegmentKind::RepositoryName => repository_name(context),
+        SegmentKind::Operation => operation(context),
+        SegmentKind::Branch => branch(context),
+        SegmentKind::Upstream => upstream(context),
+        SegmentKind::Dirty => dirty(context),
+        SegmentKind::Staged => staged(context),
+        SegmentKind::Unstaged => unstaged(context),
+        SegmentKind::Untracked => untracked(context),
+        SegmentKind::Stash => stash(context),
+        SegmentKind::Lfs => lfs(context),
+        SegmentKind::LineCounts => line_counts(context),
+        SegmentKind::Ticket => ticket(context),
+        SegmentKind::Tag => tag(context),
+        SegmentKind::AheadBehind => ahead_behind(context),
+        SegmentKind::BehindDefaultBranch => behind_default_branch(context),
+        SegmentKind::CommitAge => commit_age(context),
+        SegmentKind::CommitSubject => commit_subject(context),
+        SegmentKind::Conflicts => conflicts(context),
+        SegmentKind::UnpushedTotal => unpushed_total(context),
+        SegmentKind::IndexLock => index_lock(context),
+        SegmentKind::Skips => skips(context),
+        SegmentKind::Signing => signing(context),
+        SegmentKind::Identity => identity(context),
+        SegmentKind::Sparse => sparse(context),
+    }
+}
+
+fn host_icon(context: &Context) -> Vec<ZshOutput> {
+    if !context.config.nerd_font {
+        return Vec::new();
+    }
+
+    remote::origin_url(context.repository)
+        .and_then(|origin| origin.provider_icon())
+        .map(ZshOutput::new)
+        .into_iter()
+        .collect()
+}
+
+fn repository_name(context: &Context) -> Vec<ZshOutput> {
+    match remote::origin_url(context.repository) {
+        Some(origin) => {
+            let mut output = ZshOutput::new(&origin.path);
+            output.set_color(&context.config.repository_name_color);
+            vec![output]
+        }
+        None => Vec::new(),
+    }
+}
+
+fn operation(context: &Context) -> Vec<ZshOutput> {
+    let repository = context.repository;
+    let config = context.config;
+
+    let (label, color) = match repository.state() {
+        RepositoryState::Clean => return Vec::new(),
+        RepositoryState::Merge => {
+            let git_dir = repository.path();
+            let label = match operation::merge_branch(git_dir).or_else(|| {
+                operation::merge_head(git_dir)
+                    .map(|sha| abbreviate_sha(&sha, config.sha_length).to_string())
+            }) {
+                Some(branch) => format!("(merging {})", branch),
+                None => "(merging)".to_string(),
+            };
+            (label, &config.merge_color)
+        }
+        RepositoryState::Revert | RepositoryState::RevertSequence => {
+            ("(reverting)".to_string(), &config.revert_color)
+        }
+        RepositoryState::CherryPick | RepositoryState::CherryPickSequence => {
+            let label = match git::describe_cherry_pick(repository, config.sha_length) {
+                Some(commit) => format!("(cherry-picking {})", commit),
+                None => "(cherry-picking)".to_string(),
+            };
+            (label, &config.cherry_pick_color)
+        }
+        RepositoryState::Bisect => {
+            let label = match git::bisect_steps_left(repository) {
+                Some(steps) => format!("(bisecting ~{} steps left)", steps),
+                None => "(bisecting)".to_string(),
+            };
+            (label, &config.bisect_color)
+        }
+        RepositoryState::Rebase
+        | RepositoryState::RebaseInteractive
+        | RepositoryState::RebaseMerge => {
+            let label = operation::rebase_progress(repository.path())
+                .map(|progress| progress.label())
+                .unwrap_or_else(|| "(rebasing)".to_string());
+            (label, &config.rebase_color)
+        }
+        RepositoryState::ApplyMailbox | RepositoryState::ApplyMailboxOrRebase => {
+            let label = match operation::am_progress(repository.path()) {
+                Some((current, total)) => format!("(am {}/{})", current, total),
+                None => "(mailbox-applying)".to_string(),
+            };
+            (label, &config.am_color)
+        }
+    };
+
+    let mut output = ZshOutput::new(&label);
+    output.set_color(color);
+    vec![output]
+}
+
+fn branch(context: &Context) -> Vec<ZshOutput> {
+    let config = context.config;
+
+    let head = match context.head() {
+        Some(head) => head,
+        None => {
+            let text = match git::unborn_branch_name(context.repository) {
+                Some(branch_name) => format!("{} (no commits yet)", branch_name),
+                None => "(no commits yet)".to_string(),
+            };
+            let mut output = ZshOutput::new(&text);
+            output.set_color(&config.unborn_branch_color);
+            return vec![output];
+        }
+    };
+
+    let (stripped_prefix, short_branch_name) = if head.is_branch {
+        strip_branch_prefix(&head.name, config)
+    } else {
+        ("", head.name.as_str())
+    };
+
+    let mut branch_text = match config.branch_max_length {
+        Some(max_length) if head.is_branch => match config.branch_truncation {
+            Truncation::Tail => truncate(short_branch_name, max_length, &config.ellipsis_symbol),
+            Truncation::Middle => {
+                truncate_middle(short_branch_name, max_length, &config.ellipsis_symbol)
+            }
+        },
+        _ => short_branch_name.to_string(),
+    };
+    if config.show_sha && head.is_branch {
+        if let Some(oid) = head.oid {
+            branch_text.push('@');
+            branch_text.push_str(abbreviate_sha(&oid.to_string(), config.sha_length));
+        }
+    }
+
+    let is_upstream_gone = context
+        .branch_name()
+        .and_then(|branch_name| git::upstream(context.repository, branch_name))
+        .is_some_and(|upstream| upstream.is_gone);
+
+    let is_protected = head.is_branch
+        && config
+            .protected_branches
+            .iter()
+            .any(|protected_branch| protected_branch == &head.name);
+
+    let mut branch = ZshOutput::new(&branch_text);
+    if config.hyperlinks && head.is_branch {
+        if let Some(origin) = remote::origin_url(context.repository) {
+            branch.set_link(&origin.branch_url(&head.name));
+        }
+    }
+    if is_upstream_gone {
+        branch.set_color(&config.gone_branch_color);
+    } else if is_protected {
+        branch.set_color(&config.protected_branch_color);
+        branch.make_bold();
+    } else if context.branch_shows_dirty && context.changes().is_some_and(Changes::is_dirty) {
+        branch.set_color(&config.dirty_branch_color);
+    } else {
+        branch.set_color(&config.clean_branch_color);
+    }
+
+    let mut outputs = Vec::new();
+
+    if config.show_stripped_prefix && !stripped_prefix.is_empty() {
+        let mut prefix = ZshOutput::new(stripped_prefix);
+        prefix.set_color(&config.stripped_prefix_color);
+        outputs.push(prefix);
+    }
+
+    outputs.push(branch);
+
+    if context.scan.pathspec.is_some() {
+        let mut output = ZshOutput::new(&config.scope_symbol);
+        output.set_color(&config.scope_color);
+        outputs.push(output);
+    }
+
+    if context.branch_shows_dirty && context.changes().is_none() {
+        let mut output = ZshOutput::new(&config.dirty_unknown_symbol);
+        output.set_color(&config.dirty_unknown_color);
+        outputs.push(output);
+    }
+
+    if is_upstream_gone {
+        let text = format!("{}upstream", config.upstream_gone_symbol);
+        let mut output = ZshOutput::new(&text);
+        output.set_color(&config.gone_branch_color);
+        outputs.push(output);
+    }
+
+    outputs
+}
+
+fn upstream(context: &Context) -> Vec<ZshOutput> {
+    let upstream = match context
+        .branch_name()
+        .and_then(|branch_name| git::upstream(context.repository, branch_name))
+    {
+        Some(upstream) => upstream,
+        None => return Vec::new(),
+    };
+
+    let text = format!("{}{}", context.config.upstream_symbol, upstream.name);
+    let mut output = ZshOutput::new(&text);
+    if upstream.is_gone {
+        output.set_color(&context.config.upstream_gone_color);
+    } else {
+        output.set_color(&context.config.upstream_color);
+    }
+    vec![output]
+}
+
+fn dirty(context: &Context) -> Vec<ZshOutput> {
+    if context.head().is_none() {
+        return Vec::new();
+    }
+
+    // Whatever held up the scan would hold up the submodules too.
+    let (deleted_count, renamed_count) = match context.changes() {
+        Some(changes) => (changes.deleted_files, changes.renamed_files),
+        None => return Vec::new(),
+    };
+
+    let config = context.config;
+    let mut outputs = Vec::new();
+
+    outputs.extend(staged(context));
+    outputs.extend(unstaged(context));
+    outputs.extend(untracked(context));
+
+    if deleted_count > 0 {
+        let text = format!("{}{}", config.deleted_symbol, deleted_count);
+        let mut output = ZshOutput::new(&text);
+        output.set_color(&config.deleted_color);
+        outputs.push(output);
+    }
+
+    if renamed_count > 0 {
+        let text = format!("{}{}", config.renamed_symbol, renamed_count);
+        let mut output = ZshOutput::new(&text);
+        output.set_color(&config.renamed_color);
+        outputs.push(output);
+    }
+
+    if context.any_submodules_dirty() {
+        let mut output = ZshOutput::new(&config.submodule_symbol);
+        output.set_color(&config.submodule_color);
+        outputs.push(output);
+    }
+
+    outputs
+}
+
+fn staged(context: &Context) -> Vec<ZshOutput> {
+    let config = context.config;
+    let changes = match context.head().and_then(|_| context.changes()) {
+        Some(changes) if changes.is_staged() => changes,
+        _ => return Vec::new(),
+    };
+
+    let text = format_file_count(&config.staged_symbol, changes.staged_files, config);
+    let mut output = ZshOutput::new(&text);
+    output.set_color(&config.staged_color);
+    vec![output]
+}
+
+fn unstaged(context: &Context) -> Vec<ZshOutput> {
+    let config = context.config;
+    let changes = match context.head().and_then(|_| context.changes()) {
+        Some(changes) if changes.is_changed() => changes,
+        _ => return Vec::new(),
+    };
+
+    let text = format_file_count(&config.unstaged_symbol, changes.unstaged_files, config);
+    let mut output = ZshOutput::new(&text);
+    output.set_color(&config.unstaged_color);
+    vec![output]
+}
+
+fn untracked(context: &Context) -> Vec<ZshOutput> {
+    let config = context.config;
+    let untracked_count = match context
+        .head()
+        .and_then(|_| context.changes())
+        .and_then(|changes| changes.untracked_count)
+    {
+        Some(untracked_count) if untracked_count > 0 => untracked_count,
+        _ => return Vec::new(),
+    };
+
+    let text = format!("{}{}", config.untracked_symbol, untracked_count);
+    let mut untracked = ZshOutput::new(&text);
+    untracked.set_color(&config.untracked_color);
+    vec![untracked]
+}
+
+fn stash(context: &Context) -> Vec<ZshOutput> {
+    let stash_count = context.count_stashes().unwrap_or(0);
+    if stash_count == 0 {
+        return Vec::new();
+    }
+
+    let text = format!("{}{}", context.config.stash_symbol, stash_count);
+    let mut stash = ZshOutput::new(&text);
+    stash.set_color(&context.config.stash_color);
+    vec![stash]
+}
+
+fn lfs(context: &Context) -> Vec<ZshOutput> {
+    if !lfs::has_pending_objects(context.repository) {
+        return Vec::new();
+    }
+
+    let mut output = ZshOutput::new(&context.config.lfs_symbol);
+    output.set_color(&context.config.lfs_color);
+    vec![output]
+}
+
+fn line_counts(context: &Context) -> Vec<ZshOutput> {
+    let (insertions, deletions) = match context.head().and_then(|_| context.changes()) {
+        Some(changes) => (changes.insertions, changes.deletions),
+        _ => return Vec::new(),
+    };
+    let mut outputs = Vec::new();
+
+    if insertions > 0 {
+        let text = format!("{}{}", context.config.insertions_symbol, insertions);
+        let mut output = ZshOutput::new(&text);
+        output.set_color(&context.config.insertions_color);
+        outputs.push(output);
+    }
+
+    if deletions > 0 {
+        if !outputs.is_empty() {
+            outputs.push(ZshOutput::new(" "));
+        }
+
+        let text = format!("{}{}", context.config.deletions_symbol, deletions);
+        let mut output = ZshOutput::new(&text);
+        output.set_color(&context.config.deletions_color);
+        outputs.push(output);
+    }
+
+    outputs
+}
+
+fn ticket(context: &Context) -> Vec<ZshOutput> {
+    let ticket = match context
+        .branch_name()
+        .and_then(|branch_name| extract_ticket(branch_name, context.config))
+    {
+        Some(ticket) => ticket,
+        None => return Vec::new(),
+    };
+
+    let mut output = ZshOutput::new(&ticket);
+    if let Some(ticket_url) = context
+        .config
+        .ticket_url
+        .as_ref()
+        .filter(|_| context.config.hyperlinks)
+    {
+        output.set_link(&ticket_url.replace("{ticket}", &ticket));
+    }
+    output.set_color(&context.config.ticket_color);
+    vec![output]
+}
+
+fn tag(context: &Context) -> Vec<ZshOutput> {
+    if context.branch_name().is_none() {
+        return Vec::new();
+    }
+
+    match git::head_tag(context.repository) {
+        Some(tag) => {
+            let text = format!("{}{}", context.config.tag_symbol, tag);
+            let mut output = ZshOutput::new(&text);
+            output.set_color(&context.config.tag_color);
+            vec![output]
+        }
+        None => Vec::new(),
+    }
+}
+
+fn ahead_behind(context: &Context) -> Vec<ZshOutput> {
+    let (ahead, behind) = match context.ahead_behind() {
+        Some(ahead_behind) => ahead_behind,
+        None => return Vec::new(),
+    };
+
+    if ahead > 0 && behind > 0 {
+        let text = format!(
+            "{}{}",
+            context.config.diverged_symbol,
+            format_ahead_behind(ahead, behind, context.config)
+        );
+        let mut output = ZshOutput::new(&text);
+        output.set_color(&context.config.diverged_color);
+        output.make_bold();
+        vec![output]
+    } else if ahead > 0 || behind > 0 {
+        let mut output = ZshOutput::new(&format_ahead_behind(ahead, behind, context.config));
+        output.set_color(&context.config.ahead_behind_color);
+        vec![output]
+    } else {
+        Vec::new()
+    }
+}
+
+fn behind_default_branch(context: &Context) -> Vec<ZshOutput> {
+    let default_branch = match git::behind_default_branch(context.repository) {
+        Some(default_branch) => default_branch,
+        None => return Vec::new(),
+    };
+
+    if default_branch.behind == 0 || context.branch_name() == Some(&default_branch.name) {
+        return Vec::new();
+    }
+
+    let text = format!(
+        "{}{} vs {}",
+        context.config.behind_default_branch_symbol, default_branch.behind, default_branch.name
+    );
+    let mut output = ZshOutput::new(&text);
+    output.set_color(&context.config.behind_default_branch_color);
+    vec![output]
+}
+
+fn commit_age(context: &Context) -> Vec<ZshOutput> {
+    let config = context.config;
+
+    match git::commit_age_seconds(context.repository) {
+        Some(age) => {
+            let mut output = ZshOutput::new(&format_age(age));
+            if age >= config.commit_age_stale_seconds {
+                output.set_color(&config.commit_age_stale_color);
+            } else if age >= config.commit_age_warning_seconds {
+                output.set_color(&config.commit_age_warning_color);
+            } else {
+                output.set_color(&config.commit_age_color);
+            }
+            vec![output]
+        }
+        None => Vec::new(),
+    }
+}
+
+fn commit_subject(context: &Context) -> Vec<ZshOutput> {
+    match git::head_commit_subject(context.repository) {
+        Some(subject) => {
+            let config = context.config;
+            let text = format!(
+                "{} \"{}\"",
+                config.commit_subject_symbol,
+                truncate(
+                    &subject,
+                    config.commit_subject_length,
+                    &config.ellipsis_symbol
+                )
+            );
+            let mut output = ZshOutput::new(&text);
+            output.set_color(&config.commit_subject_color);
+            vec![output]
+        }
+        None => Vec::new(),
+    }
+}
+
+fn conflicts(context: &Context) -> Vec<ZshOutput> {
+    let conflicted_count = match context.changes() {
+        Some(changes) if changes.conflicted_files > 0 => changes.conflicted_files,
+        _ => return Vec::new(),
+    };
+
+    let text = format!("{}{}", context.config.conflicted_symbol, conflicted_count);
+    let mut conflicted = ZshOutput::new(&text);
+    conflicted.set_color(&context.config.conflicted_color);
+    conflicted.make_bold();
+    vec![conflicted]
+}
+
+fn unpushed_total(context: &Context) -> Vec<ZshOutput> {
+    match git::count_unpushed_commits(context.repository) {
+        Some(unpushed) if unpushed > 0 => {
+            let text = format!("{}{}", context.config.unpushed_total_symbol, unpushed);
+            let mut output = ZshOutput::new(&text);
+            output.set_color(&context.config.unpushed_total_color);
+            vec![output]
+        }
+        _ => Vec::new(),
+    }
+}
+
+fn index_lock(context: &Context) -> Vec<ZshOutput> {
+    if !context.repository.path().join("index.lock").exists() {
+        return Vec::new();
+    }
+
+    let mut index_lock = ZshOutput::new(&context.config.index_lock_symbol);
+    index_lock.set_color(&context.config.index_lock_color);
+    vec![index_lock]
+}
+
+fn skips(context: &Context) -> Vec<ZshOutput> {
+    if !git::any_skipped_index_entries(context.repository) {
+        return Vec::new();
+    }
+
+    let mut skips = ZshOutput::new(&context.config.skips_symbol);
+    skips.set_color(&context.config.skips_color);
+    vec![skips]
+}
+
+fn signing(context: &Context) -> Vec<ZshOutput> {
+    let symbol = &context.config.signing_symbol;
+
+    match git::commit_signing(context.repository) {
+        git::Signing::Disabled => Vec::new(),
+        git::Signing::Enabled => {
+            let mut signing = ZshOutput::new(symbol);
+            signing.set_color(&context.config.signing_color);
+            vec![signing]
+        }
+        git::Signing::MissingKey => {
+            let text = format!("{}!", symbol);
+            let mut signing = ZshOutput::new(&text);
+            signing.set_color(&context.config.signing_missing_key_color);
+            vec![signing]
+        }
+    }
+}
+
+fn identity(context: &Context) -> Vec<ZshOutput> {
+    if !git::identity_needs_attention(context.repository) {
+        return Vec::new();
+    }
+
+    let mut identity = ZshOutput::new(&context.config.identity_symbol);
+    identity.set_color(&context.config.identity_color);
+    vec![identity]
+}
+
+fn sparse(context: &Context) -> Vec<ZshOutput> {
+    if !git::is_sparse_checkout(context.repository) {
+        return Vec::new();
+    }
+
+    let text = format!("{}sparse", context.config.sparse_symbol);
+    let mut sparse = ZshOutput::new(&text);
+    sparse.set_color(&context.config.sparse_color);
+    vec![sparse]
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::process;
+
+    use git2::Signature;
+
+    use super::*;
+    use crate::cli::Shell;
+    use crate::zsh_output::{Renderer, Target};
+
+    const PLAIN: Renderer = Renderer {
+        target: Target::Shell(Shell::Zsh),
+        styled: false,
+    };
+
+    // A repository with one commit of one file, in a directory of its own.
+    fn repository(name: &str) -> Repository {
+        let workdir = std::env::temp_dir().join(format!("mfzgp-{}-{}", process::id(), name));
+        let _ = fs::remove_dir_all(&workdir);
+        let repository = Repository::init(&workdir).unwrap();
+        fs::write(workdir.join("tracked"), "one\n").unwrap();
+
+        let mut index = repository.index().unwrap();
+        index.add_path(Path::new("tracked")).unwrap();
+        index.write().unwrap();
+        let tree = repository.find_tree(index.write_tree().unwrap()).unwrap();
+        let signature = Signature::now("Test", "test@example.com").unwrap();
+        repository
+            .commit(Some("HEAD"), &signature, &signature, "First", &tree, &[])
+            .unwrap();
+        drop(tree);
+
+        repository
+    }
+
+    fn render(repository: &Repository, config: &Config, kind: SegmentKind) -> String {
+        let workdir = repository.workdir().unwrap();
+        summarize_segments(
+            repository,
+            config,
+            &Environment::current(),
+            workdir,
+            &[kind],
+        )
+        .remove(0)
+        .map(|segment| segment.output(PLAIN))
+        .unwrap_or_default()
+    }
+
+    #[test]
+    fn untracked_files_are_left_out_of_the_dirty_segment_without_show_untracked() {
+        let repository = repository("untracked");
+        let workdir = repository.workdir().unwrap().to_path_buf();
+        fs::write(workdir.join("new"), "").unwrap();
+
+        let config = Config::default();
+        assert_eq!(render(&repository, &config, SegmentKind::Dirty), "…1");
+
+        let config = Config {
+            show_untracked: false,
+            ..Config::default()
+        };
+        assert_eq!(render(&repository, &config, SegmentKind::Dirty), "");
+
+        fs::remove_dir_all(workdir).unwrap();
+    }
+
+    #[test]
+    fn a_deleted_file_is_only_counted_as_deleted() {
+        let repository = repository("deleted");
+        let workdir = repository.workdir().unwrap().to_path_buf();
+        fs::remove_file(workdir.join("tracked")).unwrap();
+
+        let config = Config::default();
+        let deleted = format!("{}1", config.deleted_symbol);
+        assert_eq!(render(&repository, &config, SegmentKind::Dirty), deleted);
+
+        let mut index = repository.index().unwrap();
+        index.remove_path(Path::new("tracked")).unwrap();
+        index.write().unwrap();
+        assert_eq!(render(&repository, &config, SegmentKind::Dirty), deleted);
+
+        fs::remove_dir_all(workdir).unwrap();
+    }
+
+    #[test]
+    fn the_tag_segment_names_a_tag_on_head() {
+        let repository = repository("tag");
+        let workdir = repository.workdir().unwrap().to_path_buf();
+        let config = Config::default();
+        let head = repository.head().unwrap().peel_to_commit().unwrap();
+        assert_eq!(render(&repository, &config, SegmentKind::Tag), "");
+
+        let signature = Signature::now("Test", "test@example.com").unwrap();
+        repository
+            .tag("v2", head.as_object(), &signature, "Second", false)
+            .unwrap();
+        let tagged = format!("{}v2", config.tag_symbol);
+        assert_eq!(render(&repository, &config, SegmentKind::Tag), tagged);
+
+        repository
+            .tag_lightweight("v1", head.as_object(), false)
+            .unwrap();
+        let tagged = format!("{}v1", config.tag_symbol);
+        assert_eq!(render(&repository, &config, SegmentKind::Tag), tagged);
+
+        fs::remove_dir_all(workdir).unwrap();
+    }
+
+    #[test]
+    fn nothing_is_unpushed_until_the_remote_has_been_fetched() {
+        let repository = repository("unpushed");
+        let workdir = repository.workdir().unwrap().to_path_buf();
+        repository
+            .remote("origin", "https://example.com/repo.git")
+            .unwrap();
+
+        let config = Config::default();
+        assert_eq!(render(&repository, &config, SegmentKind::UnpushedTotal), "");
+
+        let head = repository.head().unwrap().target().unwrap();
+        repository
+            .reference("refs/remotes/origin/other", head, false, "fetched")
+            .unwrap();
+        fs::write(workdir.join("tracked"), "two\n").unwrap();
+        let mut index = repository.index().unwrap();
+        index.add_path(Path::new("tracked")).unwrap();
+        let tree = repository.find_tree(index.write_tree().unwrap()).unwrap();
+        let parent = repository.find_commit(head).unwrap();
+        let signature = Signature::now("Test", "test@example.com").unwrap();
+        repository
+            .commit(
+                Some("HEAD"),
+                &signature,
+                &signature,
+                "Second",
+                &tree,
+                &[&parent],
+            )
+            .unwrap();
+        let unpushed = format!("{}1", config.unpushed_total_symbol);
+        assert_eq!(
+            render(&repository, &config, SegmentKind::UnpushedTotal),
+            unpushed
+        );
+
+        fs::remove_dir_all(workdir).unwrap();
+    }
+
+    #[test]
+    fn lfs_pointers_only_count_in_files_tracked_with_lfs() {
+        let repository = repository("lfs");
+        let workdir = repository.workdir().unwrap().to_path_buf();
+        fs::create_dir(repository.path().join("lfs")).unwrap();
+        fs::write(
+            workdir.join("big.bin"),
+            "version https://git-lfs.github.com/spec/v1\noid sha256:0\nsize 1\n",
+        )
+        .unwrap();
+        let mut index = repository.index().unwrap();
+        index.add_path(Path::new("big.bin")).unwrap();
+        index.write().unwrap();
+
+        let config = Config::default();
+        assert_eq!(render(&repository, &config, SegmentKind::Lfs), "");
+
+        fs::write(
+            workdir.join(".gitattributes"),
+            "*.bin filter=lfs diff=lfs\n",
+        )
+        .unwrap();
+        index.add_path(Path::new(".gitattributes")).unwrap();
+        index.write().unwrap();
+        assert_eq!(
+            render(&repository, &config, SegmentKind::Lfs),
+            config.lfs_symbol
+        );
+
+        fs::remove_dir_all(workdir).unwrap();
+    }
+
+    #[test]
+    fn the_branch_is_only_coloured_dirty_when_the_work_tree_is_scanned_anyway() {
+        let repository = repository("branch");
+        let workdir = repository.workdir().unwrap().to_path_buf();
+        fs::write(workdir.join("tracked"), "two\n").unwrap();
+        let config = Config {
+            clean_branch_color: "blue".into(),
+            dirty_branch_color: "red".into(),
+            protected_branches: Vec::new(),
+            ..Config::default()
+        };
+        let styled = Renderer {
+            styled: true,
+            ..PLAIN
+        };
+        let branch = |kinds: &[SegmentKind]| {
+            summarize_segments(
+                &repository,
+                &config,
+                &Environment::current(),
+                &workdir,
+                kinds,
+            )
+            .remove(0)
+            .map(|segment| segment.output(styled))
+            .unwrap_or_default()
+        };
+
+        assert!(branch(&[SegmentKind::Branch]).contains("%F{blue}"));
+        assert!(branch(&[SegmentKind::Branch, SegmentKind::Dirty]).contains("%F{red}"));
+
+        fs::remove_dir_all(workdir).unwrap();
+    }
+}
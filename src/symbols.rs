@@ -0,0 +1,135 @@
+use serde::Deserialize;
+
+// The built-in symbol sets. Any individual `*_symbol` setting still wins over the set.
+#[derive(Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SymbolSet {
+    Ascii,
+    Unicode,
+    NerdFont,
+}
+
+impl SymbolSet {
+    pub fn symbols(self) -> &'static [(&'static str, &'static str)] {
+        match self {
+            SymbolSet::Ascii => ASCII,
+            SymbolSet::Unicode => UNICODE,
+            SymbolSet::NerdFont => NERD_FONT,
+        }
+    }
+}
+
+const ASCII: &[(&str, &str)] = &[
+    ("staged_symbol", "+"),
+    ("unstaged_symbol", "*"),
+    ("untracked_symbol", "?"),
+    ("dirty_unknown_symbol", "?"),
+    ("scope_symbol", "./"),
+    ("conflicted_symbol", "!"),
+    ("deleted_symbol", "x"),
+    ("renamed_symbol", ">"),
+    ("detached_symbol", "@"),
+    ("ahead_symbol", "^"),
+    ("behind_symbol", "v"),
+    ("upstream_symbol", "->"),
+    ("tag_symbol", "#"),
+    ("upstream_gone_symbol", "x"),
+    ("diverged_symbol", "<>"),
+    ("unpushed_total_symbol", "^*"),
+    ("behind_default_branch_symbol", "v"),
+    ("submodule_symbol", "S"),
+    ("lfs_symbol", "LFS!"),
+    ("sparse_symbol", "~"),
+    ("identity_symbol", "@!"),
+    ("signing_symbol", "sig"),
+    ("index_lock_symbol", "LOCK"),
+    ("skips_symbol", "(skips)"),
+    ("stash_symbol", "$"),
+    ("powerline_symbol", ">"),
+    ("ellipsis_symbol", "..."),
+    ("insertions_symbol", "+"),
+    ("deletions_symbol", "-"),
+    ("commit_subject_symbol", "-"),
+    ("exit_status_symbol", "x"),
+    ("vicmd_symbol", "N"),
+    ("viins_symbol", "I"),
+    ("prompt_char_symbol", "%"),
+    ("root_prompt_char_symbol", "#"),
+];
+
+const UNICODE: &[(&str, &str)] = &[
+    ("staged_symbol", "+"),
+    ("unstaged_symbol", "*"),
+    ("untracked_symbol", "…"),
+    ("dirty_unknown_symbol", "?"),
+    ("scope_symbol", "◎"),
+    ("conflicted_symbol", "✖"),
+    ("deleted_symbol", "✘"),
+    ("renamed_symbol", "»"),
+    ("detached_symbol", "➦"),
+    ("ahead_symbol", "↑"),
+    ("behind_symbol", "↓"),
+    ("upstream_symbol", "→"),
+    ("tag_symbol", "⌂"),
+    ("upstream_gone_symbol", "✗"),
+    ("diverged_symbol", "⇕"),
+    ("unpushed_total_symbol", "⇡*"),
+    ("behind_default_branch_symbol", "⇣"),
+    ("submodule_symbol", "◧"),
+    ("lfs_symbol", "LFS↯"),
+    ("sparse_symbol", "◌"),
+    ("identity_symbol", "✉!"),
+    ("signing_symbol", "🔏"),
+    ("index_lock_symbol", "🔒"),
+    ("skips_symbol", "(skips)"),
+    ("stash_symbol", "≡"),
+    ("powerline_symbol", "▶"),
+    ("ellipsis_symbol", "…"),
+    ("insertions_symbol", "+"),
+    ("deletions_symbol", "−"),
+    ("commit_subject_symbol", "—"),
+    ("exit_status_symbol", "✘"),
+    ("vicmd_symbol", "❮"),
+    ("viins_symbol", "❯"),
+    ("prompt_char_symbol", "❯"),
+    ("root_prompt_char_symbol", "#"),
+];
+
+// Font Awesome and Powerline glyphs from the Nerd Fonts private use area.
+const NERD_FONT: &[(&str, &str)] = &[
+    ("staged_symbol", "\u{f00c}"),
+    ("unstaged_symbol", "\u{f040}"),
+    ("untracked_symbol", "\u{f128}"),
+    ("dirty_unknown_symbol", "\u{f059}"),
+    ("scope_symbol", "\u{f07c}"),
+    ("conflicted_symbol", "\u{f057}"),
+    ("deleted_symbol", "\u{f1f8}"),
+    ("renamed_symbol", "\u{f061}"),
+    ("detached_symbol", "\u{f417}"),
+    ("ahead_symbol", "\u{f062}"),
+    ("behind_symbol", "\u{f063}"),
+    ("upstream_symbol", "\u{f0c1}"),
+    ("tag_symbol", "\u{f02b}"),
+    ("upstream_gone_symbol", "\u{f127}"),
+    ("diverged_symbol", "\u{f07d}"),
+    ("unpushed_total_symbol", "\u{f093}"),
+    ("behind_default_branch_symbol", "\u{f019}"),
+    ("submodule_symbol", "\u{f1b3}"),
+    ("lfs_symbol", "\u{f0ed}"),
+    ("sparse_symbol", "\u{f10c}"),
+    ("identity_symbol", "\u{f007}"),
+    ("signing_symbol", "\u{f084}"),
+    ("index_lock_symbol", "\u{f023}"),
+    ("skips_symbol", "\u{f05e}"),
+    ("stash_symbol", "\u{f01c}"),
+    ("powerline_symbol", "\u{e0b0}"),
+    ("ellipsis_symbol", "…"),
+    ("insertions_symbol", "+"),
+    ("deletions_symbol", "−"),
+    ("commit_subject_symbol", "—"),
+    ("exit_status_symbol", "\u{f00d}"),
+    ("vicmd_symbol", "❮"),
+    ("viins_symbol", "❯"),
+    ("prompt_char_symbol", "❯"),
+    ("root_prompt_char_symbol", "#"),
+];
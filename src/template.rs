@@ -0,0 +1,207 @@
+use crate::zsh_output::{escape, Renderer, ZshOutput};
+
+// A prompt layout like `{path} [red bold]{branch}[/]{dirty}`. `{name}` is replaced with a
+// segment, `[style ...]` starts a styled span that runs until `[/]`, and doubling a brace or
+// bracket (`{{`, `]]`) produces a literal one.
+pub struct Template {
+    nodes: Vec<Node>,
+}
+
+enum Node {
+    Text(String),
+    Placeholder(String),
+    Span { style: String, nodes: Vec<Node> },
+}
+
+impl Template {
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let mut chars = source.chars().peekable();
+        let nodes = parse_nodes(&mut chars, false)?;
+
+        Ok(Template { nodes })
+    }
+
+    pub fn placeholders(&self) -> Vec<&str> {
+        let mut names = Vec::new();
+        collect_placeholders(&self.nodes, &mut names);
+        names
+    }
+
+    pub fn render<F>(&self, value: F, renderer: Renderer) -> String
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        render_nodes(&self.nodes, &value, renderer)
+    }
+}
+
+type Chars<'a> = std::iter::Peekable<std::str::Chars<'a>>;
+
+fn parse_nodes(chars: &mut Chars, in_span: bool) -> Result<Vec<Node>, String> {
+    let mut nodes = Vec::new();
+    let mut text = String::new();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '{' | '[' | '}' | ']' if chars.peek() == Some(&c) => {
+                chars.next();
+                text.push(c);
+            }
+            '{' => {
+                let name = read_until(chars, '}')?;
+                push_text(&mut nodes, &mut text);
+                nodes.push(Node::Placeholder(name.trim().to_string()));
+            }
+            '[' => {
+                let style = read_until(chars, ']')?;
+                push_text(&mut nodes, &mut text);
+
+                if style == "/" {
+                    if in_span {
+                        return Ok(nodes);
+                    }
+                    return Err("`[/]` without a matching style".to_string());
+                }
+
+                nodes.push(Node::Span {
+                    style,
+                    nodes: parse_nodes(chars, true)?,
+                });
+            }
+            _ => text.push(c),
+        }
+    }
+
+    if in_span {
+        return Err("style is never closed with `[/]`".to_string());
+    }
+
+    push_text(&mut nodes, &mut text);
+    Ok(nodes)
+}
+
+fn read_until(chars: &mut Chars, end: char) -> Result<String, String> {
+    let mut result = String::new();
+
+    for c in chars.by_ref() {
+        if c == end {
+            return Ok(result);
+        }
+        result.push(c);
+    }
+
+    Err(format!("missing `{}`", end))
+}
+
+fn push_text(nodes: &mut Vec<Node>, text: &mut String) {
+    if !text.is_empty() {
+        nodes.push(Node::Text(std::mem::take(text)));
+    }
+}
+
+fn collect_placeholders<'a>(nodes: &'a [Node], names: &mut Vec<&'a str>) {
+    for node in nodes {
+        match node {
+            Node::Text(_) => {}
+            Node::Placeholder(name) => names.push(name),
+            Node::Span { nodes, .. } => collect_placeholders(nodes, names),
+        }
+    }
+}
+
+fn render_nodes<F>(nodes: &[Node], value: &F, renderer: Renderer) -> String
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let mut result = String::new();
+
+    for node in nodes {
+        match node {
+            Node::Text(text) => result.push_str(&escape(text, renderer.target)),
+            Node::Placeholder(name) => result.push_str(&value(name).unwrap_or_default()),
+            Node::Span { style, nodes } => {
+                let inner = render_nodes(nodes, value, renderer);
+                if inner.is_empty() {
+                    continue;
+                }
+
+                let mut output = ZshOutput::new("");
+                output.apply_style(style);
+                result.push_str(&output.wrap(&inner, renderer));
+            }
+        }
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::Shell;
+    use crate::zsh_output::Target;
+
+    const PLAIN: Renderer = Renderer {
+        target: Target::Shell(Shell::Zsh),
+        styled: false,
+    };
+
+    fn render(source: &str) -> String {
+        let value = |name: &str| match name {
+            "path" => Some("~/src".to_string()),
+            "branch" => Some("main".to_string()),
+            _ => None,
+        };
+        Template::parse(source).unwrap().render(value, PLAIN)
+    }
+
+    #[test]
+    fn plain_text_comes_out_as_it_went_in() {
+        assert_eq!(render("on the left "), "on the left ");
+        assert!(Template::parse("on the left")
+            .unwrap()
+            .placeholders()
+            .is_empty());
+    }
+
+    #[test]
+    fn placeholders_are_replaced_with_their_segments() {
+        assert_eq!(render("{path} on { branch }"), "~/src on main");
+        assert_eq!(
+            Template::parse("{path} [red]{branch}[/]")
+                .unwrap()
+                .placeholders(),
+            vec!["path", "branch"]
+        );
+    }
+
+    #[test]
+    fn doubled_braces_and_brackets_are_literal() {
+        assert_eq!(render("{{path}} [[x]]"), "{path} [x]");
+        assert!(Template::parse("{{path}}")
+            .unwrap()
+            .placeholders()
+            .is_empty());
+    }
+
+    #[test]
+    fn unknown_names_parse_and_render_as_nothing() {
+        let template = Template::parse("{nonsense}{path}").unwrap();
+
+        assert_eq!(template.placeholders(), vec!["nonsense", "path"]);
+        assert_eq!(render("{nonsense}{path}"), "~/src");
+    }
+
+    #[test]
+    fn empty_spans_are_left_out() {
+        assert_eq!(render("[red]{nonsense}[/]{branch}"), "main");
+    }
+
+    #[test]
+    fn unterminated_placeholders_and_spans_are_errors() {
+        assert_eq!(Template::parse("{path").err().unwrap(), "missing `}`");
+        assert_eq!(Template::parse("[red {path}").err().unwrap(), "missing `]`");
+        assert!(Template::parse("[red]{path}").is_err());
+        assert!(Template::parse("{path}[/]").is_err());
+    }
+}
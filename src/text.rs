@@ -0,0 +1,131 @@
+pub fn abbreviate_sha(sha: &str, length: usize) -> &str {
+    sha.get(..length).unwrap_or(sha)
+}
+
+// Lengths are in characters. With no room for more than the ellipsis, it's all that's left, cut
+// down to fit too.
+pub fn truncate(text: &str, max_length: usize, ellipsis: &str) -> String {
+    if text.chars().count() <= max_length {
+        return text.to_string();
+    }
+
+    let kept = max_length.saturating_sub(ellipsis.chars().count());
+    let mut result: String = text.chars().take(kept).collect();
+    result.extend(ellipsis.chars().take(max_length));
+    result
+}
+
+// Keeps the end instead, for paths.
+pub fn truncate_start(text: &str, max_length: usize, ellipsis: &str) -> String {
+    let length = text.chars().count();
+    if length <= max_length {
+        return text.to_string();
+    }
+
+    let kept = max_length.saturating_sub(ellipsis.chars().count());
+    let mut result: String = ellipsis.chars().take(max_length).collect();
+    result.extend(text.chars().skip(length - kept));
+    result
+}
+
+pub fn truncate_middle(text: &str, max_length: usize, ellipsis: &str) -> String {
+    let length = text.chars().count();
+    if length <= max_length {
+        return text.to_string();
+    }
+
+    let kept = max_length.saturating_sub(ellipsis.chars().count());
+    let head_length = kept - kept / 2;
+    let tail_length = kept / 2;
+
+    let mut result: String = text.chars().take(head_length).collect();
+    result.extend(ellipsis.chars().take(max_length));
+    result.extend(text.chars().skip(length - tail_length));
+    result
+}
+
+pub fn format_age(seconds: u64) -> String {
+    if seconds < 60 * 60 {
+        format!("{}m", seconds / 60)
+    } else if seconds < 60 * 60 * 24 {
+        format!("{}h", seconds / (60 * 60))
+    } else {
+        format!("{}d", seconds / (60 * 60 * 24))
+    }
+}
+
+// Like `3.2s` under a minute, then `1m12s`, then `2h5m`.
+pub fn format_duration(milliseconds: u64) -> String {
+    let seconds = milliseconds / 1000;
+
+    if seconds < 60 {
+        format!("{}.{}s", seconds, milliseconds % 1000 / 100)
+    } else if seconds < 60 * 60 {
+        format!("{}m{}s", seconds / 60, seconds % 60)
+    } else {
+        format!("{}h{}m", seconds / (60 * 60), seconds % (60 * 60) / 60)
+    }
+}
+
+// Percent-encodes everything but unreserved characters and `/`, for putting paths in URLs.
+pub fn percent_encode(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+
+    for byte in text.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                result.push(byte as char)
+            }
+            byte => result.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn text_that_fits_is_left_alone() {
+        assert_eq!(truncate("main", 4, "…"), "main");
+        assert_eq!(truncate_start("~/src", 5, "…"), "~/src");
+        assert_eq!(truncate_middle("feature", 7, "…"), "feature");
+    }
+
+    #[test]
+    fn keeps_the_start_the_end_or_both() {
+        assert_eq!(truncate("feature/login", 8, "…"), "feature…");
+        assert_eq!(truncate_start("feature/login", 8, "…"), "…e/login");
+        assert_eq!(truncate_middle("feature/login", 8, "…"), "feat…gin");
+        assert_eq!(truncate_middle("feature/login", 8, "..."), "fea...in");
+    }
+
+    #[test]
+    fn abbreviating_a_sha_never_splits_a_character() {
+        assert_eq!(abbreviate_sha("0123456789abcdef", 7), "0123456");
+        assert_eq!(abbreviate_sha("0123", 7), "0123");
+        assert_eq!(abbreviate_sha("012345ü789", 7), "012345ü789");
+    }
+
+    #[test]
+    fn counts_characters_rather_than_bytes() {
+        assert_eq!(truncate_start("ünïcödé-brånch", 6, "…"), "…rånch");
+        assert_eq!(truncate_middle("ünïcödé-brånch", 7, "…"), "ünï…nch");
+        assert_eq!(truncate_middle("日本語のブランチ", 5, "…"), "日本…ンチ");
+        assert_eq!(truncate_start("日本語のブランチ", 4, "…"), "…ランチ");
+    }
+
+    #[test]
+    fn no_room_for_more_than_the_ellipsis_leaves_only_the_ellipsis() {
+        assert_eq!(truncate_middle("feature/login", 1, "…"), "…");
+        assert_eq!(truncate_start("feature/login", 1, "…"), "…");
+        assert_eq!(truncate_middle("feature/login", 3, "..."), "...");
+        assert_eq!(truncate_start("feature/login", 3, "..."), "...");
+        assert_eq!(truncate_middle("feature/login", 2, "..."), "..");
+        assert_eq!(truncate_start("feature/login", 2, "..."), "..");
+        assert_eq!(truncate("feature/login", 2, "..."), "..");
+        assert_eq!(truncate_middle("feature/login", 0, "…"), "");
+    }
+}
@@ -0,0 +1,19 @@
+// Named bundles of settings. A theme only supplies defaults, so anything set in a config file,
+// the repository or the environment still wins.
+const THEMES: &[(&str, &str)] = &[
+    ("classic", include_str!("themes/classic.toml")),
+    ("minimal", include_str!("themes/minimal.toml")),
+    ("powerline", include_str!("themes/powerline.toml")),
+    ("nerd", include_str!("themes/nerd.toml")),
+];
+
+pub fn theme(name: &str) -> Option<toml::Table> {
+    THEMES
+        .iter()
+        .find(|(theme_name, _)| *theme_name == name)
+        .map(|(_, contents)| contents.parse().expect("built-in themes are valid TOML"))
+}
+
+pub fn names() -> impl Iterator<Item = &'static str> {
+    THEMES.iter().map(|(name, _)| *name)
+}
@@ -0,0 +1,23 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+// Runs `work`, and with `--timings` reports how long it took on stderr. Steps inside other steps
+// finish first, so they're printed first.
+pub fn time<T>(label: &str, work: impl FnOnce() -> T) -> T {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return work();
+    }
+
+    let start = Instant::now();
+    let result = work();
+    let elapsed = start.elapsed().as_secs_f64() * 1000.0;
+    eprintln!("{:>9.3}ms  {}", elapsed, label);
+
+    result
+}
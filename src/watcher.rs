@@ -0,0 +1,463 @@
+use std::path::PathBuf;
+
+#[cfg(target_os = "linux")]
+use std::collections::{HashMap, HashSet};
+#[cfg(target_os = "linux")]
+use std::ffi::{CString, OsString};
+#[cfg(target_os = "linux")]
+use std::fs;
+#[cfg(target_os = "linux")]
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::os::unix::io::RawFd;
+use std::path::Path;
+
+#[cfg(target_os = "linux")]
+use git2::Index;
+use git2::Repository;
+
+#[cfg(target_os = "linux")]
+use crate::cache;
+
+// Why the daemon woke up.
+pub enum Wake {
+    Connection,
+    // The git directories of the repositories whose prompts need working out again, because
+    // something changed in them or in the config.
+    Changed(Vec<PathBuf>),
+}
+
+// Tells the daemon when something the prompt shows may have changed in a repository: HEAD, the
+// index, a ref, or a file in the work tree. Ignored directories aren't watched, and changes to
+// ignored files don't count, so a build running in the background isn't a change. While a
+// repository is watched its summaries are kept in memory.
+#[cfg(target_os = "linux")]
+pub struct Watcher {
+    fd: RawFd,
+    watches: HashMap<i32, Watch>,
+    // The watches for each repository, by git directory.
+    repositories: HashMap<PathBuf, Vec<i32>>,
+    // The config files being watched, by the watch on the directory they're in, since editors
+    // tend to replace a file rather than write to it.
+    configs: HashMap<i32, Vec<OsString>>,
+    // Repositories with more directories than `LIMIT`, so they aren't walked again on every
+    // prompt only to be given up on.
+    too_large: HashSet<PathBuf>,
+}
+
+// Linked worktrees share their refs, so a directory can be watched for more than one repository.
+#[cfg(target_os = "linux")]
+struct Watch {
+    path: PathBuf,
+    git_dirs: Vec<PathBuf>,
+}
+
+#[cfg(target_os = "linux")]
+const EVENTS: u32 = libc::IN_CREATE
+    | libc::IN_DELETE
+    | libc::IN_MODIFY
+    | libc::IN_ATTRIB
+    | libc::IN_MOVED_FROM
+    | libc::IN_MOVED_TO
+    | libc::IN_ONLYDIR;
+
+#[cfg(target_os = "linux")]
+impl Watcher {
+    // Directories per repository. A bigger one isn't watched at all, and its prompts are worked
+    // out every time, as they would be without the daemon.
+    const LIMIT: usize = 8192;
+    const REPOSITORY_LIMIT: usize = 64;
+    // How long things have to stay quiet before a burst of changes, like a checkout, is over.
+    const SETTLE_MS: i32 = 50;
+
+    pub fn new() -> Option<Self> {
+        let fd = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+        if fd < 0 {
+            return None;
+        }
+
+        Some(Watcher {
+            fd,
+            watches: HashMap::new(),
+            repositories: HashMap::new(),
+            configs: HashMap::new(),
+            too_large: HashSet::new(),
+        })
+    }
+
+    // All of the repository or none of it, since a summary can only be kept while every change
+    // would be seen. Like the open repositories, they're all dropped once there are too many.
+    pub fn watch(&mut self, repository: &Repository) {
+        let git_dir = repository.path().to_path_buf();
+        if self.repositories.contains_key(&git_dir) || self.too_large.contains(&git_dir) {
+            return;
+        }
+        if self.repositories.len() >= Self::REPOSITORY_LIMIT {
+            let git_dirs: Vec<PathBuf> = self.repositories.keys().cloned().collect();
+            for git_dir in git_dirs {
+                self.unwatch(&git_dir);
+            }
+        }
+
+        let mut dirs = Vec::new();
+        let complete = git_dirs(&git_dir, &mut dirs)
+            && common_dir(&git_dir).is_none_or(|common_dir| git_dirs(&common_dir, &mut dirs))
+            && repository
+                .workdir()
+                .is_none_or(|workdir| worktree_dirs(repository, workdir, &mut dirs));
+        if !complete {
+            self.too_large.insert(git_dir);
+            return;
+        }
+
+        self.repositories.insert(git_dir.clone(), Vec::new());
+        for dir in dirs {
+            if !self.add(&git_dir, dir) {
+                return self.unwatch(&git_dir);
+            }
+        }
+        cache::keep_in_memory(&git_dir);
+    }
+
+    pub fn watch_config(&mut self, path: &Path) {
+        let (dir, name) = match (path.parent(), path.file_name()) {
+            (Some(dir), Some(name)) => (dir, name.to_os_string()),
+            _ => return,
+        };
+        let dir = match CString::new(dir.as_os_str().as_bytes()) {
+            Ok(dir) => dir,
+            Err(_) => return,
+        };
+        let wd = unsafe { libc::inotify_add_watch(self.fd, dir.as_ptr(), EVENTS) };
+        if wd < 0 {
+            return;
+        }
+
+        let names = self.configs.entry(wd).or_default();
+        if !names.contains(&name) {
+            names.push(name);
+        }
+    }
+
+    fn add(&mut self, git_dir: &Path, dir: PathBuf) -> bool {
+        let path = match CString::new(dir.as_os_str().as_bytes()) {
+            Ok(path) => path,
+            Err(_) => return false,
+        };
+        let wd = unsafe { libc::inotify_add_watch(self.fd, path.as_ptr(), EVENTS) };
+        if wd < 0 {
+            return false;
+        }
+
+        let watch = self.watches.entry(wd).or_insert_with(|| Watch {
+            path: dir,
+            git_dirs: Vec::new(),
+        });
+        if !watch.git_dirs.iter().any(|other| other == git_dir) {
+            watch.git_dirs.push(git_dir.to_path_buf());
+            if let Some(wds) = self.repositories.get_mut(git_dir) {
+                wds.push(wd);
+            }
+        }
+        true
+    }
+
+    fn unwatch(&mut self, git_dir: &Path) {
+        for wd in self.repositories.remove(git_dir).unwrap_or_default() {
+            let unused = match self.watches.get_mut(&wd) {
+                Some(watch) => {
+                    watch.git_dirs.retain(|other| other != git_dir);
+                    watch.git_dirs.is_empty()
+                }
+                None => false,
+            };
+            if unused {
+                self.watches.remove(&wd);
+                unsafe {
+                    libc::inotify_rm_watch(self.fd, wd);
+                }
+            }
+        }
+        cache::stop_keeping_in_memory(git_dir);
+    }
+
+    // Blocks until there's a connection waiting on `listener`, or something has changed and then
+    // stayed quiet for a moment. A connection cuts the wait for quiet short.
+    pub fn wait(&mut self, listener: RawFd) -> Wake {
+        let mut changed = Vec::new();
+
+        loop {
+            let mut fds = [
+                libc::pollfd {
+                    fd: listener,
+                    events: libc::POLLIN,
+                    revents: 0,
+                },
+                libc::pollfd {
+                    fd: self.fd,
+                    events: libc::POLLIN,
+                    revents: 0,
+                },
+            ];
+            let timeout = if changed.is_empty() {
+                -1
+            } else {
+                Self::SETTLE_MS
+            };
+            let ready = unsafe { libc::poll(fds.as_mut_ptr(), 2, timeout) };
+
+            if ready < 0 {
+                continue;
+            }
+            if fds[1].revents & libc::POLLIN != 0 {
+                for git_dir in self.read_events() {
+                    if !changed.contains(&git_dir) {
+                        changed.push(git_dir);
+                    }
+                }
+            }
+            if ready == 0 || (fds[0].revents & libc::POLLIN != 0 && !changed.is_empty()) {
+                return Wake::Changed(changed);
+            }
+            if fds[0].revents & libc::POLLIN != 0 {
+                return Wake::Connection;
+            }
+        }
+    }
+
+    // New directories get watched as they appear, unless they're ignored. A change to the config
+    // means every prompt needs working out again, but what's remembered about the repositories is
+    // still right for the config it was worked out with.
+    fn read_events(&mut self) -> Vec<PathBuf> {
+        let mut changed: Vec<PathBuf> = Vec::new();
+        let mut config_changed = false;
+        let mut ignores_changed: Vec<PathBuf> = Vec::new();
+        let mut repositories: HashMap<PathBuf, Option<Repository>> = HashMap::new();
+        let mut buffer = [0u8; 4096];
+        let header = std::mem::size_of::<libc::inotify_event>();
+
+        loop {
+            let length = unsafe {
+                libc::read(
+                    self.fd,
+                    buffer.as_mut_ptr() as *mut libc::c_void,
+                    buffer.len(),
+                )
+            };
+            if length <= 0 {
+                break;
+            }
+
+            let mut offset = 0;
+            while offset + header <= length as usize {
+                let event: libc::inotify_event =
+                    unsafe { std::ptr::read_unaligned(buffer.as_ptr().add(offset) as *const _) };
+                let name_end = (offset + header + event.len as usize).min(length as usize);
+                let name = &buffer[offset + header..name_end];
+                let name = &name[..name
+                    .iter()
+                    .position(|byte| *byte == 0)
+                    .unwrap_or(name.len())];
+                offset = name_end;
+
+                // Too many events to keep up with, so anything could have changed.
+                if event.mask & libc::IN_Q_OVERFLOW != 0 {
+                    changed = self.repositories.keys().cloned().collect();
+                    continue;
+                }
+                if event.mask & libc::IN_IGNORED != 0 {
+                    self.watches.remove(&event.wd);
+                    self.configs.remove(&event.wd);
+                    continue;
+                }
+                if let Some(names) = self.configs.get(&event.wd) {
+                    config_changed |= names.iter().any(|other| other.as_bytes() == name);
+                }
+
+                let (dir, git_dirs) = match self.watches.get(&event.wd) {
+                    Some(watch) => (watch.path.clone(), watch.git_dirs.clone()),
+                    None => continue,
+                };
+                let path = dir.join(OsString::from_vec(name.to_vec()));
+
+                for git_dir in git_dirs {
+                    if changes_ignores(&path) && !ignores_changed.contains(&git_dir) {
+                        ignores_changed.push(git_dir.clone());
+                    }
+
+                    let repository = repositories
+                        .entry(git_dir.clone())
+                        .or_insert_with(|| Repository::open(&git_dir).ok());
+                    let ignored = repository
+                        .as_ref()
+                        .is_some_and(|repository| is_ignored(repository, &path));
+                    if ignored {
+                        continue;
+                    }
+
+                    let is_new_dir = event.mask & libc::IN_ISDIR != 0
+                        && event.mask & (libc::IN_CREATE | libc::IN_MOVED_TO) != 0;
+                    if is_new_dir {
+                        self.watch_new_dir(&git_dir, repository.as_ref(), path.clone());
+                    }
+                    if !changed.contains(&git_dir) {
+                        changed.push(git_dir);
+                    }
+                }
+            }
+        }
+
+        for git_dir in ignores_changed {
+            self.unwatch(&git_dir);
+            self.too_large.remove(&git_dir);
+            if let Some(Some(repository)) = repositories.get(&git_dir) {
+                self.watch(repository);
+            }
+        }
+        for git_dir in &changed {
+            cache::forget(git_dir);
+        }
+        if config_changed {
+            changed = self.repositories.keys().cloned().collect();
+        }
+        changed
+    }
+
+    fn watch_new_dir(&mut self, git_dir: &Path, repository: Option<&Repository>, path: PathBuf) {
+        let mut dirs = Vec::new();
+        let complete = match repository.and_then(Repository::workdir) {
+            Some(workdir) if path.starts_with(workdir) && !path.starts_with(git_dir) => {
+                let repository = repository.unwrap();
+                walk(path, &mut dirs, |dir| !is_ignored(repository, dir))
+            }
+            _ => walk(path, &mut dirs, |dir| !dir.ends_with("objects")),
+        };
+
+        let total = self.repositories.get(git_dir).map_or(0, Vec::len) + dirs.len();
+        if !complete || total > Self::LIMIT || !dirs.into_iter().all(|dir| self.add(git_dir, dir)) {
+            self.unwatch(git_dir);
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Drop for Watcher {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.fd);
+        }
+    }
+}
+
+// Everything in the git directory except the objects, which only matter once a ref points at
+// them.
+#[cfg(target_os = "linux")]
+fn git_dirs(git_dir: &Path, dirs: &mut Vec<PathBuf>) -> bool {
+    walk(git_dir.to_path_buf(), dirs, |dir| !dir.ends_with("objects"))
+}
+
+// A linked worktree's git directory names the main one, which holds the refs.
+#[cfg(target_os = "linux")]
+fn common_dir(git_dir: &Path) -> Option<PathBuf> {
+    let contents = fs::read_to_string(git_dir.join("commondir")).ok()?;
+    Some(git_dir.join(contents.trim()))
+}
+
+#[cfg(target_os = "linux")]
+fn worktree_dirs(repository: &Repository, workdir: &Path, dirs: &mut Vec<PathBuf>) -> bool {
+    walk(workdir.to_path_buf(), dirs, |dir| {
+        !dir.ends_with(".git") && !is_ignored(repository, dir)
+    })
+}
+
+// The directories under `root`, and `root` itself, leaving out any that `include` turns down
+// along with everything under them. False if there are too many to watch.
+#[cfg(target_os = "linux")]
+fn walk(root: PathBuf, dirs: &mut Vec<PathBuf>, include: impl Fn(&Path) -> bool) -> bool {
+    let mut pending = vec![root];
+
+    while let Some(dir) = pending.pop() {
+        if dirs.len() >= Watcher::LIMIT {
+            return false;
+        }
+
+        if let Ok(entries) = fs::read_dir(&dir) {
+            for entry in entries.flatten() {
+                let path = entry.path();
+                if entry.file_type().is_ok_and(|kind| kind.is_dir()) && include(&path) {
+                    pending.push(path);
+                }
+            }
+        }
+        dirs.push(dir);
+    }
+
+    true
+}
+
+// Ignored and not tracked. A file added before the rule that ignores it, or added with `--force`,
+// still shows up in the status, so it's still watched, and so is a directory with one in it.
+#[cfg(target_os = "linux")]
+fn is_ignored(repository: &Repository, path: &Path) -> bool {
+    match repository
+        .workdir()
+        .and_then(|workdir| path.strip_prefix(workdir).ok())
+    {
+        Some(relative) if !relative.starts_with(".git") => {
+            repository.is_path_ignored(relative).unwrap_or(false)
+                && !repository
+                    .index()
+                    .is_ok_and(|index| is_tracked(&index, relative))
+        }
+        _ => false,
+    }
+}
+
+// The entries are sorted by path, so anything beneath a directory comes straight after where
+// `dir/` would go.
+#[cfg(target_os = "linux")]
+fn is_tracked(index: &Index, relative: &Path) -> bool {
+    if index.get_path(relative, 0).is_some() {
+        return true;
+    }
+
+    let mut prefix = relative.as_os_str().as_bytes().to_vec();
+    prefix.push(b'/');
+    let (mut low, mut high) = (0, index.len());
+    while low < high {
+        let middle = low + (high - low) / 2;
+        match index.get(middle) {
+            Some(entry) if entry.path < prefix => low = middle + 1,
+            _ => high = middle,
+        }
+    }
+
+    index
+        .get(low)
+        .is_some_and(|entry| entry.path.starts_with(&prefix))
+}
+
+// A change to what's ignored can bring directories into the status that were never watched.
+#[cfg(target_os = "linux")]
+fn changes_ignores(path: &Path) -> bool {
+    path.file_name().is_some_and(|name| name == ".gitignore") || path.ends_with("info/exclude")
+}
+
+// inotify is Linux's own, so elsewhere nothing is watched and the daemon works every prompt out
+// as it's asked for.
+#[cfg(not(target_os = "linux"))]
+pub struct Watcher;
+
+#[cfg(not(target_os = "linux"))]
+impl Watcher {
+    pub fn new() -> Option<Self> {
+        None
+    }
+
+    pub fn watch(&mut self, _repository: &Repository) {}
+
+    pub fn watch_config(&mut self, _path: &Path) {}
+
+    pub fn wait(&mut self, _listener: RawFd) -> Wake {
+        Wake::Connection
+    }
+}
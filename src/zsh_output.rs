@@ -0,0 +1,745 @@
+use serde::{Deserialize, Serialize};
+
+use crate::cli::Shell;
+
+// A color zsh can draw: one of the eight names (plus `default`), an index into the 256-color
+// palette, or a 24-bit `#rrggbb` value.
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub enum TermColor {
+    Named(String),
+    Indexed(u8),
+    Rgb(u8, u8, u8),
+}
+
+const COLOR_NAMES: &[&str] = &[
+    "black", "red", "green", "yellow", "blue", "magenta", "cyan", "white", "default",
+];
+
+impl TermColor {
+    pub fn parse(color: &str) -> Option<Self> {
+        let color = color.trim().to_lowercase();
+
+        if let Some(hex) = color.strip_prefix('#') {
+            return parse_hex(hex);
+        }
+
+        if let Ok(index) = color.parse() {
+            return Some(TermColor::Indexed(index));
+        }
+
+        if COLOR_NAMES.contains(&color.as_str()) {
+            return Some(TermColor::Named(color));
+        }
+
+        None
+    }
+
+    // The SGR parameters for this color, where `base` is 30 for the foreground and 40 for the
+    // background.
+    fn sgr(&self, base: u8) -> String {
+        match self {
+            TermColor::Named(name) => match COLOR_NAMES.iter().position(|other| other == name) {
+                Some(index) if index < 8 => (base + index as u8).to_string(),
+                _ => (base + 9).to_string(),
+            },
+            TermColor::Indexed(index) => format!("{};5;{}", base + 8, index),
+            TermColor::Rgb(red, green, blue) => {
+                format!("{};2;{};{};{}", base + 8, red, green, blue)
+            }
+        }
+    }
+
+    // The form tmux's `fg=` and `bg=` take.
+    fn tmux_name(&self) -> String {
+        match self {
+            TermColor::Indexed(index) => format!("colour{}", index),
+            _ => self.zsh_name(),
+        }
+    }
+
+    // The form `%F{...}` takes.
+    fn zsh_name(&self) -> String {
+        match self {
+            TermColor::Named(name) => name.clone(),
+            TermColor::Indexed(index) => index.to_string(),
+            TermColor::Rgb(red, green, blue) => format!("#{:02x}{:02x}{:02x}", red, green, blue),
+        }
+    }
+}
+
+// `rrggbb`, or the `rgb` shorthand.
+fn parse_hex(hex: &str) -> Option<TermColor> {
+    if !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    let channel = |digits: &str| u8::from_str_radix(digits, 16).ok();
+
+    match hex.len() {
+        6 => Some(TermColor::Rgb(
+            channel(&hex[0..2])?,
+            channel(&hex[2..4])?,
+            channel(&hex[4..6])?,
+        )),
+        3 => Some(TermColor::Rgb(
+            channel(&hex[0..1].repeat(2))?,
+            channel(&hex[1..2].repeat(2))?,
+            channel(&hex[2..3].repeat(2))?,
+        )),
+        _ => None,
+    }
+}
+
+// A set of text attributes, combined with `|`.
+#[derive(Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct Style(u8);
+
+impl Style {
+    pub const BOLD: Style = Style(1);
+    pub const UNDERLINE: Style = Style(1 << 1);
+    pub const STANDOUT: Style = Style(1 << 2);
+    pub const ITALIC: Style = Style(1 << 3);
+    pub const DIM: Style = Style(1 << 4);
+
+    pub fn contains(self, other: Style) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn from_name(name: &str) -> Option<Style> {
+        match name {
+            "bold" => Some(Style::BOLD),
+            "underline" => Some(Style::UNDERLINE),
+            "standout" => Some(Style::STANDOUT),
+            "italic" => Some(Style::ITALIC),
+            "dim" => Some(Style::DIM),
+            _ => None,
+        }
+    }
+}
+
+impl std::ops::BitOr for Style {
+    type Output = Style;
+
+    fn bitor(self, other: Style) -> Style {
+        Style(self.0 | other.0)
+    }
+}
+
+impl std::ops::BitOrAssign for Style {
+    fn bitor_assign(&mut self, other: Style) {
+        self.0 |= other.0;
+    }
+}
+
+// What the output is for: a shell's prompt, a tmux status line, or a Starship format string.
+#[derive(Clone, Copy)]
+pub enum Target {
+    Shell(Shell),
+    Tmux,
+    Starship,
+}
+
+// Where the output is going, and whether it should be styled at all.
+#[derive(Clone, Copy)]
+pub struct Renderer {
+    pub target: Target,
+    pub styled: bool,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ZshOutput {
+    style: Style,
+    color: Option<TermColor>,
+    background: Option<TermColor>,
+    link: Option<String>,
+    text: String,
+}
+
+impl ZshOutput {
+    pub fn new(text: &str) -> Self {
+        ZshOutput {
+            text: text.to_string(),
+            style: Style::default(),
+            color: None,
+            background: None,
+            link: None,
+        }
+    }
+
+    // Anything that isn't a color zsh understands is ignored.
+    pub fn set_color(&mut self, color: &str) {
+        if let Some(color) = TermColor::parse(color) {
+            self.color = Some(color);
+        }
+    }
+
+    pub fn set_background(&mut self, color: &str) {
+        if let Some(color) = TermColor::parse(color) {
+            self.background = Some(color);
+        }
+    }
+
+    // Makes the text an OSC 8 hyperlink, for terminals that support them.
+    pub fn set_link(&mut self, url: &str) {
+        self.link = Some(url.to_string());
+    }
+
+    pub fn make_bold(&mut self) {
+        self.add_style(Style::BOLD);
+    }
+
+    pub fn add_style(&mut self, style: Style) {
+        self.style |= style;
+    }
+
+    // A space-separated list like `red bold on blue`: an attribute (`bold`, `underline`,
+    // `standout`, `italic`, `dim`), a color, or `on` and a background color.
+    pub fn apply_style(&mut self, style: &str) {
+        let mut words = style.split_whitespace();
+
+        while let Some(word) = words.next() {
+            if let Some(attribute) = Style::from_name(word) {
+                self.add_style(attribute);
+                continue;
+            }
+
+            match word {
+                "on" => {
+                    if let Some(background) = words.next() {
+                        self.set_background(background);
+                    }
+                }
+                color => self.set_color(color),
+            }
+        }
+    }
+
+    // The text is escaped for the target here, so it can be built from anything. Without
+    // styling, only the text is left, so the layout stays the same.
+    pub fn render(&self, renderer: Renderer) -> String {
+        self.wrap(&escape(&self.text, renderer.target), renderer)
+    }
+
+    // Applies this output's style to text that has already been rendered.
+    pub fn wrap(&self, rendered: &str, renderer: Renderer) -> String {
+        let shell = match renderer.target {
+            _ if !renderer.styled => return rendered.to_string(),
+            Target::Tmux => return self.wrap_tmux(rendered),
+            Target::Starship => return self.wrap_starship(rendered),
+            Target::Shell(shell) => shell,
+        };
+
+        let mut escapes = EscapeBuilder::new(shell);
+
+        if self.style.contains(Style::BOLD) {
+            escapes.attribute(Some(("%B", "%b")), "1", "22");
+        }
+
+        if self.style.contains(Style::UNDERLINE) {
+            escapes.attribute(Some(("%U", "%u")), "4", "24");
+        }
+
+        if self.style.contains(Style::STANDOUT) {
+            escapes.attribute(Some(("%S", "%s")), "7", "27");
+        }
+
+        // zsh has no prompt escapes for these, so they're always raw ANSI.
+        if self.style.contains(Style::ITALIC) {
+            escapes.attribute(None, "3", "23");
+        }
+
+        if self.style.contains(Style::DIM) {
+            escapes.attribute(None, "2", "22");
+        }
+
+        if let Some(ref c) = self.color {
+            let zsh_open = format!("%F{{{}}}", c.zsh_name());
+            escapes.attribute(Some((&zsh_open, "%f")), &c.sgr(30), "39");
+        }
+
+        if let Some(ref c) = self.background {
+            let zsh_open = format!("%K{{{}}}", c.zsh_name());
+            escapes.attribute(Some((&zsh_open, "%k")), &c.sgr(40), "49");
+        }
+
+        if let Some(ref url) = self.link {
+            let url = escape(url, renderer.target);
+            escapes.raw(&format!("\x1b]8;;{}\x07", url), "\x1b]8;;\x07");
+        }
+
+        escapes.wrap(rendered)
+    }
+
+    // tmux takes a list of attributes in `#[...]`, and each one has its own way to turn it off.
+    // tmux doesn't do hyperlinks in its status line, so they're left out.
+    fn wrap_tmux(&self, rendered: &str) -> String {
+        let mut open = Vec::new();
+        let mut close = Vec::new();
+
+        for (style, name) in [
+            (Style::BOLD, "bold"),
+            (Style::UNDERLINE, "underscore"),
+            (Style::STANDOUT, "reverse"),
+            (Style::ITALIC, "italics"),
+            (Style::DIM, "dim"),
+        ] {
+            if self.style.contains(style) {
+                open.push(name.to_string());
+                close.push(format!("no{}", name));
+            }
+        }
+
+        if let Some(ref c) = self.color {
+            open.push(format!("fg={}", c.tmux_name()));
+            close.push("fg=default".to_string());
+        }
+
+        if let Some(ref c) = self.background {
+            open.push(format!("bg={}", c.tmux_name()));
+            close.push("bg=default".to_string());
+        }
+
+        if open.is_empty() {
+            return rendered.to_string();
+        }
+
+        format!("#[{}]{}#[{}]", open.join(","), rendered, close.join(","))
+    }
+
+    // Starship styles a `[text](style)` group, and nested groups take over from the outer one.
+    fn wrap_starship(&self, rendered: &str) -> String {
+        let mut style = Vec::new();
+
+        for (attribute, name) in [
+            (Style::BOLD, "bold"),
+            (Style::UNDERLINE, "underline"),
+            (Style::STANDOUT, "inverted"),
+            (Style::ITALIC, "italic"),
+            (Style::DIM, "dimmed"),
+        ] {
+            if self.style.contains(attribute) {
+                style.push(name.to_string());
+            }
+        }
+
+        // Starship has no name for the default color; leaving it out has the same effect.
+        let named = |c: &TermColor| c != &TermColor::Named("default".to_string());
+
+        if let Some(c) = self.color.as_ref().filter(|c| named(c)) {
+            style.push(format!("fg:{}", c.zsh_name()));
+        }
+
+        if let Some(c) = self.background.as_ref().filter(|c| named(c)) {
+            style.push(format!("bg:{}", c.zsh_name()));
+        }
+
+        if style.is_empty() {
+            return rendered.to_string();
+        }
+
+        format!("[{}]({})", rendered, style.join(" "))
+    }
+}
+
+// Collects escapes that switch something on, along with the ones that switch it back off, so
+// they always close in the reverse of the order they opened.
+struct EscapeBuilder {
+    shell: Shell,
+    open: String,
+    close: Vec<String>,
+}
+
+impl EscapeBuilder {
+    fn new(shell: Shell) -> Self {
+        EscapeBuilder {
+            shell,
+            open: String::new(),
+            close: Vec::new(),
+        }
+    }
+
+    // zsh gets its own prompt escapes like `%B` where there are some, since it already knows
+    // they take up no space. Everything else is an ANSI SGR sequence with these parameters.
+    fn attribute(&mut self, zsh: Option<(&str, &str)>, sgr_open: &str, sgr_close: &str) {
+        match (self.shell, zsh) {
+            (Shell::Zsh, Some((open, close))) => {
+                self.open.push_str(open);
+                self.close.push(close.to_string());
+            }
+            _ => self.raw(
+                &format!("\x1b[{}m", sgr_open),
+                &format!("\x1b[{}m", sgr_close),
+            ),
+        }
+    }
+
+    // Raw terminal sequences, which have to be marked as zero-width.
+    fn raw(&mut self, open: &str, close: &str) {
+        self.open.push_str(&zero_width(open, self.shell));
+        self.close.push(zero_width(close, self.shell));
+    }
+
+    fn wrap(self, text: &str) -> String {
+        let mut result = self.open;
+        result.push_str(text);
+        for close in self.close.iter().rev() {
+            result.push_str(close);
+        }
+        result
+    }
+}
+
+// Tells the shell that a raw escape sequence doesn't move the cursor, so it can measure the
+// prompt. bash decodes `\e` and `\a` itself, so they're spelled out there. fish and PowerShell
+// print whatever the prompt function returns and work out the width on their own, and plain
+// ANSI output isn't going to a shell at all.
+pub fn zero_width(sequence: &str, shell: Shell) -> String {
+    match shell {
+        Shell::Fish | Shell::Pwsh | Shell::Ansi => sequence.to_string(),
+        Shell::Zsh => format!("%{{{}%}}", sequence),
+        Shell::Bash => format!(
+            "\\[{}\\]",
+            sequence.replace('\x1b', "\\e").replace('\x07', "\\a")
+        ),
+    }
+}
+
+// Makes text from the repository or file system safe to put in the output.
+pub fn escape(text: &str, target: Target) -> String {
+    match target {
+        Target::Shell(Shell::Zsh) => escape_zsh(text),
+        Target::Shell(Shell::Bash) => escape_bash(text),
+        Target::Shell(Shell::Fish | Shell::Pwsh | Shell::Ansi) => strip_control(text),
+        Target::Tmux => escape_tmux(text),
+        Target::Starship => escape_starship(text),
+    }
+}
+
+// Brackets, parentheses and `$` have meanings in a Starship format string.
+pub fn escape_starship(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+
+    for c in strip_control(text).chars() {
+        if matches!(c, '[' | ']' | '(' | ')' | '$' | '\\') {
+            result.push('\\');
+        }
+        result.push(c);
+    }
+
+    result
+}
+
+// `#` starts a format or style in a tmux status line.
+pub fn escape_tmux(text: &str) -> String {
+    strip_control(text).replace('#', "##")
+}
+
+// Output that's printed as-is only needs to keep out terminal sequences of its own.
+pub fn strip_control(text: &str) -> String {
+    text.chars().filter(|c| !c.is_control()).collect()
+}
+
+// `%` would start a prompt escape, and since the prompt is assigned with `prompt_subst` on, `$`,
+// backticks and backslashes would otherwise be expanded too. Control characters are dropped so
+// a crafted branch name can't send its own terminal sequences.
+pub fn escape_zsh(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        match c {
+            '%' => result.push_str("%%"),
+            '$' | '`' | '\\' => {
+                result.push('\\');
+                result.push(c);
+            }
+            c if c.is_control() => {}
+            c => result.push(c),
+        }
+    }
+
+    result
+}
+
+// bash reads `PS1` twice: once to decode its backslash escapes, then again for `promptvars`
+// expansion, so a literal backslash needs to survive both and `$` and backticks need one
+// backslash left over for the second pass.
+pub fn escape_bash(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        match c {
+            '\\' => result.push_str("\\\\\\\\"),
+            '$' | '`' => {
+                result.push_str("\\\\");
+                result.push(c);
+            }
+            c if c.is_control() => {}
+            c => result.push(c),
+        }
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ZSH: Renderer = Renderer {
+        target: Target::Shell(Shell::Zsh),
+        styled: true,
+    };
+
+    const BASH: Renderer = Renderer {
+        target: Target::Shell(Shell::Bash),
+        styled: true,
+    };
+
+    const ATTRIBUTES: &[(Style, &str, &str)] = &[
+        (Style::BOLD, "%B", "%b"),
+        (Style::UNDERLINE, "%U", "%u"),
+        (Style::STANDOUT, "%S", "%s"),
+        (Style::ITALIC, "%{\x1b[3m%}", "%{\x1b[23m%}"),
+        (Style::DIM, "%{\x1b[2m%}", "%{\x1b[22m%}"),
+    ];
+
+    #[test]
+    fn plain_text_has_no_escapes() {
+        assert_eq!(ZshOutput::new("main").render(ZSH), "main");
+    }
+
+    #[test]
+    fn colors_are_closed_inside_the_braces() {
+        let mut output = ZshOutput::new("main");
+        output.set_color("red");
+
+        assert_eq!(output.render(ZSH), "%F{red}main%f");
+    }
+
+    #[test]
+    fn bold_wraps_the_color() {
+        let mut output = ZshOutput::new("main");
+        output.set_color("yellow");
+        output.make_bold();
+
+        assert_eq!(output.render(ZSH), "%B%F{yellow}main%f%b");
+    }
+
+    #[test]
+    fn backgrounds_use_k() {
+        let mut output = ZshOutput::new("main");
+        output.set_color("white");
+        output.set_background("blue");
+
+        assert_eq!(output.render(ZSH), "%F{white}%K{blue}main%k%f");
+    }
+
+    #[test]
+    fn indexed_and_hex_colors() {
+        let mut output = ZshOutput::new("main");
+        output.set_color("208");
+        output.set_background("#FFF");
+
+        assert_eq!(output.render(ZSH), "%F{208}%K{#ffffff}main%k%f");
+    }
+
+    #[test]
+    fn unknown_colors_are_ignored() {
+        let mut output = ZshOutput::new("main");
+        output.set_color("red");
+        output.set_color("not-a-color");
+
+        assert_eq!(output.render(ZSH), "%F{red}main%f");
+    }
+
+    #[test]
+    fn raw_sequences_are_zero_width() {
+        let mut output = ZshOutput::new("main");
+        output.add_style(Style::ITALIC);
+
+        assert_eq!(output.render(ZSH), "%{\x1b[3m%}main%{\x1b[23m%}");
+    }
+
+    #[test]
+    fn every_style_combination_opens_and_closes_in_order() {
+        for bits in 0..(1 << ATTRIBUTES.len()) {
+            for color in [None, Some("red")] {
+                for background in [None, Some("blue")] {
+                    let mut output = ZshOutput::new("text");
+                    let mut expected_open = String::new();
+                    let mut expected_close = Vec::new();
+
+                    for (index, (style, open, close)) in ATTRIBUTES.iter().enumerate() {
+                        if bits & (1 << index) != 0 {
+                            output.add_style(*style);
+                            expected_open.push_str(open);
+                            expected_close.push(*close);
+                        }
+                    }
+
+                    if let Some(color) = color {
+                        output.set_color(color);
+                        expected_open.push_str(&format!("%F{{{}}}", color));
+                        expected_close.push("%f");
+                    }
+
+                    if let Some(background) = background {
+                        output.set_background(background);
+                        expected_open.push_str(&format!("%K{{{}}}", background));
+                        expected_close.push("%k");
+                    }
+
+                    expected_close.reverse();
+                    let expected = format!("{}text{}", expected_open, expected_close.concat());
+
+                    assert_eq!(output.render(ZSH), expected);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn apply_style_reads_attributes_colors_and_backgrounds() {
+        let mut output = ZshOutput::new("main");
+        output.apply_style("underline red on 17 bold");
+
+        assert_eq!(output.render(ZSH), "%B%U%F{red}%K{17}main%k%f%u%b");
+    }
+
+    #[test]
+    fn unstyled_rendering_keeps_only_the_text() {
+        let mut output = ZshOutput::new("main");
+        output.apply_style("red bold underline on blue");
+
+        let renderer = Renderer {
+            target: Target::Shell(Shell::Zsh),
+            styled: false,
+        };
+
+        assert_eq!(output.render(renderer), "main");
+    }
+
+    #[test]
+    fn escape_zsh_doubles_percent_signs() {
+        assert_eq!(escape_zsh("100%"), "100%%");
+    }
+
+    #[test]
+    fn escape_zsh_quotes_expansions() {
+        assert_eq!(escape_zsh("$(rm -rf ~)`id`\\"), "\\$(rm -rf ~)\\`id\\`\\\\");
+    }
+
+    #[test]
+    fn escape_zsh_drops_control_characters() {
+        assert_eq!(escape_zsh("feature\x1b]0;pwned\x07"), "feature]0;pwned");
+    }
+
+    #[test]
+    fn rendering_escapes_the_text() {
+        assert_eq!(ZshOutput::new("50% $HOME").render(ZSH), "50%% \\$HOME");
+    }
+
+    #[test]
+    fn hyperlinks_mark_their_sequences_as_zero_width() {
+        let mut output = ZshOutput::new("link");
+        output.set_link("https://example.com/100%");
+
+        assert_eq!(
+            output.render(ZSH),
+            "%{\x1b]8;;https://example.com/100%%\x07%}link%{\x1b]8;;\x07%}"
+        );
+    }
+
+    #[test]
+    fn bash_uses_wrapped_sgr_sequences() {
+        let mut output = ZshOutput::new("main");
+        output.set_color("red");
+        output.make_bold();
+
+        assert_eq!(
+            output.render(BASH),
+            "\\[\\e[1m\\]\\[\\e[31m\\]main\\[\\e[39m\\]\\[\\e[22m\\]"
+        );
+    }
+
+    #[test]
+    fn bash_colors() {
+        let mut output = ZshOutput::new("main");
+        output.set_color("208");
+        output.set_background("#102030");
+
+        assert_eq!(
+            output.render(BASH),
+            "\\[\\e[38;5;208m\\]\\[\\e[48;2;16;32;48m\\]main\\[\\e[49m\\]\\[\\e[39m\\]"
+        );
+
+        let mut output = ZshOutput::new("main");
+        output.set_color("default");
+        output.set_background("white");
+
+        assert_eq!(
+            output.render(BASH),
+            "\\[\\e[39m\\]\\[\\e[47m\\]main\\[\\e[49m\\]\\[\\e[39m\\]"
+        );
+    }
+
+    #[test]
+    fn bash_hyperlinks() {
+        let mut output = ZshOutput::new("link");
+        output.set_link("https://example.com/");
+
+        assert_eq!(
+            output.render(BASH),
+            "\\[\\e]8;;https://example.com/\\a\\]link\\[\\e]8;;\\a\\]"
+        );
+    }
+
+    #[test]
+    fn fish_uses_raw_sgr_sequences() {
+        let renderer = Renderer {
+            target: Target::Shell(Shell::Fish),
+            styled: true,
+        };
+        let mut output = ZshOutput::new("$main%");
+        output.set_color("red");
+        output.add_style(Style::UNDERLINE);
+
+        assert_eq!(
+            output.render(renderer),
+            "\x1b[4m\x1b[31m$main%\x1b[39m\x1b[24m"
+        );
+    }
+
+    #[test]
+    fn tmux_styles_turn_off_only_what_they_turned_on() {
+        let renderer = Renderer {
+            target: Target::Tmux,
+            styled: true,
+        };
+        let mut output = ZshOutput::new("#1");
+        output.apply_style("bold 208 on #102030");
+
+        assert_eq!(
+            output.render(renderer),
+            "#[bold,fg=colour208,bg=#102030]##1#[nobold,fg=default,bg=default]"
+        );
+    }
+
+    #[test]
+    fn starship_groups_take_a_style_string() {
+        let renderer = Renderer {
+            target: Target::Starship,
+            styled: true,
+        };
+        let mut output = ZshOutput::new("[main]");
+        output.apply_style("bold italic 208 on default");
+
+        assert_eq!(output.render(renderer), "[\\[main\\]](bold italic fg:208)");
+    }
+
+    #[test]
+    fn escape_bash_survives_decoding_and_expansion() {
+        assert_eq!(escape_bash("a\\$b`c`"), "a\\\\\\\\\\\\$b\\\\`c\\\\`");
+        assert_eq!(escape_bash("100%\x1b[31m"), "100%[31m");
+    }
+}